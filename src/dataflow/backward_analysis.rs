@@ -1,22 +1,38 @@
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 
 use super::fact_base::FactBase;
-use super::graph::{Graph, Label, Language};
+use super::graph::{BasicBlock, Graph, Label, Language};
 use super::lattice::Lattice;
+use super::rewrite::{
+    assert_new_labels, burn, can_rewrite, BlockRewrite, Budget, Depths, Fuel, RewriteDepth,
+    Rewritten,
+};
 
 pub struct AnalyzeInstructionBackward<'a, F> {
     fact: &'a mut F,
+    budget: Budget<'a>,
 }
 
 impl<'a, F> AnalyzeInstructionBackward<'a, F> {
-    fn new(fact: &'a mut F) -> Self {
-        AnalyzeInstructionBackward { fact }
+    pub(super) fn new(fact: &'a mut F, budget: Budget<'a>) -> Self {
+        AnalyzeInstructionBackward { fact, budget }
+    }
+
+    pub(super) fn into_parts(self) -> (&'a mut F, Budget<'a>) {
+        (self.fact, self.budget)
     }
 
     pub fn fact(&self) -> &F {
         self.fact
     }
 
+    // Whether a rewrite would be accepted right now. When it wouldn't, the instruction is left
+    //   as it is and gets analyzed again from the same fact, which has to come back without a
+    //   rewrite.
+    pub fn can_rewrite(&self) -> bool {
+        self.budget.can_rewrite()
+    }
+
     pub fn fact_mut(self) -> &'a mut F {
         self.fact
     }
@@ -47,17 +63,29 @@ impl<'a, F> AnalyzeInstructionBackward<'a, F> {
 
 pub struct AnalyzeExitBackward<'a, F> {
     fact: &'a mut F,
+    budget: Budget<'a>,
 }
 
 impl<'a, F> AnalyzeExitBackward<'a, F> {
-    fn new(fact: &'a mut F) -> Self {
-        AnalyzeExitBackward { fact }
+    pub(super) fn new(fact: &'a mut F, budget: Budget<'a>) -> Self {
+        AnalyzeExitBackward { fact, budget }
+    }
+
+    pub(super) fn into_parts(self) -> (&'a mut F, Budget<'a>) {
+        (self.fact, self.budget)
     }
 
     pub fn fact(&self) -> &F {
         self.fact
     }
 
+    // Whether a rewrite would be accepted right now. When it wouldn't, the exit is left as it
+    //   is and gets analyzed again from the same fact, which has to come back without a
+    //   rewrite.
+    pub fn can_rewrite(&self) -> bool {
+        self.budget.can_rewrite()
+    }
+
     pub fn fact_mut(self) -> &'a mut F {
         self.fact
     }
@@ -74,6 +102,7 @@ impl<'a, F> AnalyzeExitBackward<'a, F> {
         RewriteExitBackward(RewriteExitEnum::Extend(instructions, exit))
     }
 
+    // Just like going forward, the labels of the sub-graph can't already be in the graph.
     pub fn replace_with_graph<L: Language>(
         self,
         exit: L::Exit,
@@ -84,10 +113,10 @@ impl<'a, F> AnalyzeExitBackward<'a, F> {
 }
 
 #[derive(Clone)]
-pub struct RewriteInstructionBackward<L: Language>(RewriteInstructionEnum<L>);
+pub struct RewriteInstructionBackward<L: Language>(pub(super) RewriteInstructionEnum<L>);
 
 #[derive(Clone)]
-enum RewriteInstructionEnum<L: Language> {
+pub(super) enum RewriteInstructionEnum<L: Language> {
     // Replace the currently analyzed instruction with this single, new instruction.
     Single(L::Instruction),
 
@@ -102,10 +131,10 @@ enum RewriteInstructionEnum<L: Language> {
 }
 
 #[derive(Clone)]
-pub struct RewriteExitBackward<L: Language>(RewriteExitEnum<L>);
+pub struct RewriteExitBackward<L: Language>(pub(super) RewriteExitEnum<L>);
 
 #[derive(Clone)]
-enum RewriteExitEnum<L: Language> {
+pub(super) enum RewriteExitEnum<L: Language> {
     // Replace the currently analyzed instruction with this single, new instruction.
     Single(L::Exit),
 
@@ -142,44 +171,152 @@ pub trait BackwardAnalysis<L: Language, F> {
         entry: &L::Entry,
         fact: F,
     ) -> FactBase<F>;
+
+    // Whether the code produced by a rewrite gets rewritten again. Shallow by default, so a
+    //   pass that keeps rewriting its own output can't go around forever.
+    fn rewrite_depth(&self) -> RewriteDepth {
+        RewriteDepth::Shallow
+    }
 }
 
-pub fn backward_analysis<L, A, F>(analysis: &mut A, graph: &Graph<L>, entry: Label) -> FactBase<F>
+// Every block that can't jump anywhere starts out with 'exit_fact', and every other block
+//   reachable from 'entry' starts out with the bottom fact.
+//
+// Like forward_analysis, this doesn't take any fuel since none of the rewrites are kept.
+pub fn backward_analysis<L, A, F>(
+    analysis: &mut A,
+    graph: &Graph<L>,
+    entry: Label,
+    exit_fact: F,
+) -> FactBase<F>
 where
     L: Language,
     A: BackwardAnalysis<L, F>,
     F: Lattice,
 {
-    let mut fact_base = FnvHashMap::default();
-    fixed_point_backward_graph(analysis, &graph, entry, &mut fact_base);
+    let boundary = exit_boundary(graph, entry, exit_fact);
+    backward_analysis_with_boundary(analysis, graph, entry, boundary)
+}
+
+// Like backward_analysis, but the caller decides which blocks start from which facts. Any
+//   reachable block that isn't in 'boundary' starts out with the bottom fact.
+pub fn backward_analysis_with_boundary<L, A, F>(
+    analysis: &mut A,
+    graph: &Graph<L>,
+    entry: Label,
+    boundary: FactBase<F>,
+) -> FactBase<F>
+where
+    L: Language,
+    A: BackwardAnalysis<L, F>,
+    F: Lattice,
+{
+    let mut fact_base = seed_fact_base(graph, entry, boundary);
+    let region = graph.labels().collect();
+    let depths = FnvHashMap::default();
+    fixed_point_backward_graph(analysis, graph, &region, &depths, &[entry], &mut fact_base);
     fact_base
 }
 
-fn fixed_point_backward_graph<L, A, F>(
+// Like backward_analysis, but also hands back the graph with the rewrites applied. Just like
+//   going forward, rewrites made before the fixed point is reached only feed into the facts,
+//   and the graph gets the ones made in a final pass against the final facts, which burn 'fuel'.
+pub fn analyze_and_rewrite_backward<L, A, F>(
     analysis: &mut A,
     graph: &Graph<L>,
     entry: Label,
+    exit_fact: F,
+    mut fuel: Fuel,
+) -> Rewritten<L, F>
+where
+    L: Language,
+    A: BackwardAnalysis<L, F>,
+    F: Lattice,
+{
+    let boundary = exit_boundary(graph, entry, exit_fact);
+    let mut fact_base = seed_fact_base(graph, entry, boundary);
+
+    let region = graph.labels().collect();
+    let depths = FnvHashMap::default();
+    fixed_point_backward_graph(analysis, graph, &region, &depths, &[entry], &mut fact_base);
+    let (blocks, facts) = commit_backward_graph(
+        analysis,
+        graph,
+        &region,
+        &depths,
+        &[entry],
+        &fact_base,
+        &mut fuel,
+    );
+    fact_base.extend(facts);
+
+    Rewritten {
+        graph: Graph::from_blocks(blocks),
+        facts: fact_base,
+        fuel_used: fuel.used(),
+    }
+}
+
+fn exit_boundary<L: Language, F: Clone>(
+    graph: &Graph<L>,
+    entry: Label,
+    exit_fact: F,
+) -> FactBase<F> {
+    let mut boundary = FnvHashMap::default();
+    for label in graph.post_order_traversal(entry) {
+        if graph[label].successors().is_empty() {
+            boundary.insert(label, exit_fact.clone());
+        }
+    }
+    boundary
+}
+
+fn seed_fact_base<L: Language, F: Lattice>(
+    graph: &Graph<L>,
+    entry: Label,
+    boundary: FactBase<F>,
+) -> FactBase<F> {
+    let mut fact_base = boundary;
+    for label in graph.post_order_traversal(entry) {
+        fact_base.entry(label).or_insert_with(F::bottom);
+    }
+    fact_base
+}
+
+fn fixed_point_backward_graph<L, A, F>(
+    analysis: &mut A,
+    graph: &Graph<L>,
+    region: &FnvHashSet<Label>,
+    depths: &FnvHashMap<Label, Depths>,
+    entries: &[Label],
     fact_base: &mut FactBase<F>,
 ) where
     L: Language,
     A: BackwardAnalysis<L, F>,
     F: Lattice,
 {
-    let mut to_visit = graph.post_order_traversal(entry);
+    let mut to_visit = graph.post_order_within(entries, region);
     to_visit.reverse();
 
     while let Some(label) = to_visit.pop() {
-        if !graph.contains(label) {
+        if !region.contains(&label) {
             // We don't need to analyze any blocks outside of our sub graph.
             continue;
         }
 
-        let output_fact_base = fixed_point_backward_block(analysis, &graph, label, fact_base);
+        let fact = fact_base
+            .get(&label)
+            .expect("We should always have a fact to start from");
+        let rewrite = fixed_point_backward_block(analysis, graph, label, fact, depths, None);
 
-        for predecessor in graph.direct_predecessors(label) {
+        for &predecessor in graph.predecessors(label) {
+            let fact = match rewrite.output.get(&predecessor) {
+                Some(fact) => fact,
+                None => continue,
+            };
             let old_fact = fact_base.entry(predecessor).or_insert_with(F::bottom);
 
-            if !old_fact.join(&output_fact_base[&predecessor], predecessor) {
+            if !old_fact.join(fact, predecessor) {
                 // We didn't change so we don't need to re-examine this predecessor
                 continue;
             }
@@ -191,39 +328,135 @@ fn fixed_point_backward_graph<L, A, F>(
     }
 }
 
+// Rewrites every block in the region one last time, now that 'fact_base' holds the final facts,
+//   and returns the rewritten blocks along with the facts for any blocks that were spliced in.
+fn commit_backward_graph<L, A, F>(
+    analysis: &mut A,
+    graph: &Graph<L>,
+    region: &FnvHashSet<Label>,
+    depths: &FnvHashMap<Label, Depths>,
+    entries: &[Label],
+    fact_base: &FactBase<F>,
+    fuel: &mut Fuel,
+) -> (Vec<BasicBlock<L>>, FactBase<F>)
+where
+    L: Language,
+    A: BackwardAnalysis<L, F>,
+    F: Lattice,
+{
+    let mut blocks = vec![];
+    let mut facts = FnvHashMap::default();
+    let mut committed = FnvHashSet::default();
+
+    for label in graph.post_order_within(entries, region) {
+        if let Some(fact) = fact_base.get(&label) {
+            let rewrite =
+                fixed_point_backward_block(analysis, graph, label, fact, depths, Some(fuel));
+            blocks.extend(rewrite.blocks);
+            facts.extend(rewrite.facts);
+            committed.insert(label);
+        }
+    }
+
+    // Blocks we never reached are left exactly as they were.
+    for label in region {
+        if !committed.contains(label) {
+            blocks.push(graph[*label].clone());
+        }
+    }
+
+    (blocks, facts)
+}
+
 fn fixed_point_backward_block<L, A, F>(
     analysis: &mut A,
     graph: &Graph<L>,
     label: Label,
-    fact_base: &FactBase<F>,
-) -> FactBase<F>
+    exit_fact: &F,
+    depths: &FnvHashMap<Label, Depths>,
+    mut fuel: Option<&mut Fuel>,
+) -> BlockRewrite<L, F>
 where
     L: Language,
     A: BackwardAnalysis<L, F>,
     F: Lattice,
 {
-    let mut fact = fact_base
-        .get(&label)
-        .expect("We should always have a fact to start from")
-        .clone();
+    let mut fact = exit_fact.clone();
     let mut block = graph[label].clone();
+    let mut depths = Depths::of(depths, &block);
+    let rewrite_depth = analysis.rewrite_depth();
+
+    loop {
+        let depth = depths.exit;
+        let rewrite = can_rewrite(&fuel) && rewrite_depth.allows(depth);
+        let before = (!rewrite).then(|| fact.clone());
+        let result = analysis.analyze_exit(
+            graph,
+            label,
+            &block.exit,
+            AnalyzeExitBackward::new(&mut fact, Budget::new(rewrite, depth, fuel.as_deref_mut())),
+        );
+        let result = match (result, before) {
+            (None, _) => break,
+            (Some(_), Some(before)) => {
+                // Same as with instructions, the exit stays and gets analyzed again.
+                fact = before;
+                let result = analysis.analyze_exit(
+                    graph,
+                    label,
+                    &block.exit,
+                    AnalyzeExitBackward::new(&mut fact, Budget::none()),
+                );
+                assert!(
+                    result.is_none(),
+                    "Exits can only be rewritten when can_rewrite() allows it"
+                );
+                break;
+            }
+            (Some(result), None) => result,
+        };
+        burn(&mut fuel);
 
-    while let Some(rewrite) = analysis.analyze_exit(
-        graph,
-        label,
-        &block.exit,
-        AnalyzeExitBackward::new(&mut fact),
-    ) {
-        match rewrite {
+        match result {
             RewriteExitBackward(RewriteExitEnum::Single(exit)) => {
                 block.exit = exit;
+                depths.exit = depth + 1;
             }
             RewriteExitBackward(RewriteExitEnum::Extend(instructions, exit)) => {
+                depths
+                    .code
+                    .extend(std::iter::repeat(depth + 1).take(instructions.len()));
+                depths.exit = depth + 1;
                 block.code.extend(instructions);
                 block.exit = exit;
             }
-            RewriteExitBackward(RewriteExitEnum::Graph(_exit, _sub_graph)) => {
-                panic!("not implemented yet");
+            RewriteExitBackward(RewriteExitEnum::Graph(exit, sub_graph)) => {
+                // The sub-graph now sits between this block and its old successors, so the
+                //   block has to be analyzed together with it. The blocks of the sub-graph that
+                //   jump back out of it start from the fact that used to flow out of the block.
+                block.exit = exit;
+                depths.exit = depth + 1;
+                let mut spliced_depths = FnvHashMap::default();
+                spliced_depths.insert(label, depths);
+                let mut starts = FnvHashMap::default();
+                for sub_block in sub_graph.blocks() {
+                    let leaves = sub_block
+                        .successors()
+                        .iter()
+                        .any(|&successor| successor != label && !sub_graph.contains(successor));
+                    let start = if leaves { fact.clone() } else { F::bottom() };
+                    starts.insert(sub_block.label(), start);
+                    spliced_depths.insert(sub_block.label(), Depths::new(sub_block, depth + 1));
+                }
+                return fixed_point_backward_splice(
+                    analysis,
+                    graph,
+                    block,
+                    sub_graph.blocks().cloned().collect(),
+                    &spliced_depths,
+                    starts,
+                    fuel,
+                );
             }
         }
     }
@@ -231,29 +464,170 @@ where
     let mut counter = 0;
     while counter < block.code.len() {
         let index = block.code.len() - (counter + 1);
-        match analysis.analyze_instruction(
+        let depth = depths.code[index];
+        let rewrite = can_rewrite(&fuel) && rewrite_depth.allows(depth);
+        let before = (!rewrite).then(|| fact.clone());
+        let result = analysis.analyze_instruction(
             graph,
             label,
             &block.code[index],
-            AnalyzeInstructionBackward::new(&mut fact),
-        ) {
+            AnalyzeInstructionBackward::new(
+                &mut fact,
+                Budget::new(rewrite, depth, fuel.as_deref_mut()),
+            ),
+        );
+        if result.is_some() {
+            if let Some(before) = before {
+                // Same as going forward, the instruction stays and gets analyzed again.
+                fact = before;
+                let result = analysis.analyze_instruction(
+                    graph,
+                    label,
+                    &block.code[index],
+                    AnalyzeInstructionBackward::new(&mut fact, Budget::none()),
+                );
+                assert!(
+                    result.is_none(),
+                    "Instructions can only be rewritten when can_rewrite() allows it"
+                );
+                counter += 1;
+                continue;
+            }
+            burn(&mut fuel);
+        }
+
+        match result {
             Some(RewriteInstructionBackward(RewriteInstructionEnum::Single(inst))) => {
                 block.code[index] = inst;
+                depths.replace(index, 1);
             }
             Some(RewriteInstructionBackward(RewriteInstructionEnum::Multiple(insts))) => {
+                depths.replace(index, insts.len());
                 block.code.splice(index..index + 1, insts);
             }
             Some(RewriteInstructionBackward(RewriteInstructionEnum::Graph(
-                _exit,
-                _sub_graph,
-                _entry,
+                exit,
+                sub_graph,
+                entry,
             ))) => {
-                panic!("Unimplemented");
+                // Same as going forward, the block ends in a jump into the sub-graph and the
+                //   rest of its code moves into a new block the sub-graph jumps back into. That
+                //   new block starts over from the fact flowing out of the original one, and
+                //   the code left in this block gets analyzed along with the sub-graph.
+                let depth = depths.code[index];
+                let rest = block.code.split_off(index + 1);
+                block.code.pop();
+                let rest_exit = std::mem::replace(&mut block.exit, exit);
+                let rest_depths = Depths {
+                    code: depths.code.split_off(index + 1),
+                    exit: std::mem::replace(&mut depths.exit, depth + 1),
+                };
+                depths.code.pop();
+
+                let rest_block = BasicBlock::new(entry, rest, rest_exit);
+                let mut spliced_depths = FnvHashMap::default();
+                spliced_depths.insert(label, depths);
+                spliced_depths.insert(rest_block.label(), rest_depths);
+                let mut starts = FnvHashMap::default();
+                starts.insert(rest_block.label(), exit_fact.clone());
+                let mut spliced = vec![rest_block];
+                for sub_block in sub_graph.blocks() {
+                    spliced_depths.insert(sub_block.label(), Depths::new(sub_block, depth + 1));
+                    starts.insert(sub_block.label(), F::bottom());
+                    spliced.push(sub_block.clone());
+                }
+                return fixed_point_backward_splice(
+                    analysis,
+                    graph,
+                    block,
+                    spliced,
+                    &spliced_depths,
+                    starts,
+                    fuel,
+                );
             }
             None => {
                 counter += 1;
             }
         }
     }
-    analysis.analyze_entry(&graph, label, &block.entry, fact)
+
+    let output = analysis.analyze_entry(graph, label, &block.entry, fact);
+    BlockRewrite {
+        blocks: vec![block],
+        facts: FnvHashMap::default(),
+        output,
+    }
+}
+
+// Runs a fixed point over 'block' and the blocks spliced in after it, each of which starts from
+//   its fact in 'starts'. 'depths' has to cover 'block' as well as the spliced blocks.
+//   Whatever flows out of 'block' at the end is the output of the whole.
+fn fixed_point_backward_splice<L, A, F>(
+    analysis: &mut A,
+    graph: &Graph<L>,
+    block: BasicBlock<L>,
+    spliced: Vec<BasicBlock<L>>,
+    depths: &FnvHashMap<Label, Depths>,
+    starts: FactBase<F>,
+    fuel: Option<&mut Fuel>,
+) -> BlockRewrite<L, F>
+where
+    L: Language,
+    A: BackwardAnalysis<L, F>,
+    F: Lattice,
+{
+    assert_new_labels(graph, &spliced);
+    let label = block.label();
+    let mut region: FnvHashSet<Label> = spliced.iter().map(BasicBlock::label).collect();
+    region.insert(label);
+
+    // The analysis sees the spliced blocks in a copy of the graph where they've been put in.
+    let mut spliced_graph = graph.clone();
+    spliced_graph.insert_block(block);
+    for spliced_block in spliced {
+        spliced_graph.insert_block(spliced_block);
+    }
+
+    let mut fact_base = starts;
+    fact_base.insert(label, F::bottom());
+
+    fixed_point_backward_graph(
+        analysis,
+        &spliced_graph,
+        &region,
+        depths,
+        &[label],
+        &mut fact_base,
+    );
+
+    let committed = fuel.map(|fuel| {
+        commit_backward_graph(
+            analysis,
+            &spliced_graph,
+            &region,
+            depths,
+            &[label],
+            &fact_base,
+            fuel,
+        )
+    });
+
+    let mut rewrite = BlockRewrite {
+        blocks: vec![],
+        facts: FnvHashMap::default(),
+        output: FnvHashMap::default(),
+    };
+    for (label, fact) in fact_base {
+        if region.contains(&label) {
+            rewrite.facts.insert(label, fact);
+        } else {
+            rewrite.output.insert(label, fact);
+        }
+    }
+    if let Some((blocks, facts)) = committed {
+        rewrite.blocks.extend(blocks);
+        rewrite.facts.extend(facts);
+    }
+    rewrite
 }
@@ -0,0 +1,1104 @@
+use fnv::FnvHashMap;
+
+use super::backward_analysis::{
+    self as backward, AnalyzeExitBackward, AnalyzeInstructionBackward, BackwardAnalysis,
+    RewriteExitBackward, RewriteInstructionBackward,
+};
+use super::fact_base::FactBase;
+use super::forward_analysis::{
+    self as forward, AnalyzeExit, AnalyzeInstruction, ForwardAnalysis, RewriteExit,
+    RewriteInstruction,
+};
+use super::graph::{Graph, Label, Language};
+use super::lattice::Lattice;
+use super::rewrite::{Budget, RewriteDepth};
+
+// Runs two analyses at once, each over its own half of a pair of facts. Either of them may
+//   rewrite, the first one getting the first go, and whatever they rewrite to gets analyzed by
+//   both of them again.
+pub struct Pair<A, B>(pub A, pub B);
+
+// Two rewriters over the same facts. The first one is in charge of the facts, and the second
+//   one only gets a go at rewriting whatever the first one leaves alone.
+pub struct OrElse<A, B>(pub A, pub B);
+
+// Like OrElse, but when the first one does rewrite something, the second one gets a go at what
+//   it was rewritten into, as part of the same rewrite. Only instructions or exits rewritten
+//   into other instructions or exits are handed on like this, sub-graphs are left as they are.
+//   What the first one rewrote to is a rewrite deeper than what it rewrote, so the second one
+//   can only rewrite it with a deep enough rewrite depth, and each of its rewrites burns fuel
+//   on top of the first one's.
+pub struct Then<A, B>(pub A, pub B);
+
+// Splits a forward exit rewrite into the facts it sends out when it's done, or the rewrite
+//   itself, now for facts of some other type.
+fn exit_facts<L: Language, F, G>(
+    rewrite: RewriteExit<L, F>,
+) -> Result<FactBase<F>, RewriteExit<L, G>> {
+    match rewrite {
+        RewriteExit::Done(facts) => Ok(facts),
+        RewriteExit::Single(exit) => Err(RewriteExit::Single(exit)),
+        RewriteExit::Extend(instructions, exit) => Err(RewriteExit::Extend(instructions, exit)),
+        RewriteExit::Graph(exit, sub_graph) => Err(RewriteExit::Graph(exit, sub_graph)),
+    }
+}
+
+// Any label missing from one side starts from the bottom fact on that side.
+fn zip_facts<A: Lattice, B: Lattice>(
+    first: FactBase<A>,
+    mut second: FactBase<B>,
+) -> FactBase<(A, B)> {
+    let mut facts = FnvHashMap::default();
+    for (label, a) in first {
+        let b = second.remove(&label).unwrap_or_else(B::bottom);
+        facts.insert(label, (a, b));
+    }
+    for (label, b) in second {
+        facts.insert(label, (A::bottom(), b));
+    }
+    facts
+}
+
+impl<L, A, B, FA, FB> ForwardAnalysis<L, (FA, FB)> for Pair<A, B>
+where
+    L: Language,
+    A: ForwardAnalysis<L, FA>,
+    B: ForwardAnalysis<L, FB>,
+    FA: Lattice,
+    FB: Lattice,
+{
+    fn analyze_entry(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        entry: &L::Entry,
+        fact: (FA, FB),
+    ) -> (FA, FB) {
+        (
+            self.0.analyze_entry(graph, label, entry, fact.0),
+            self.1.analyze_entry(graph, label, entry, fact.1),
+        )
+    }
+
+    fn analyze_entry_edges(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        entry: &L::Entry,
+        incoming: &FactBase<(FA, FB)>,
+        fact: (FA, FB),
+    ) -> (FA, FB) {
+        let (first, second): (FactBase<FA>, FactBase<FB>) = incoming
+            .iter()
+            .map(|(predecessor, (a, b))| ((*predecessor, a.clone()), (*predecessor, b.clone())))
+            .unzip();
+        (
+            self.0
+                .analyze_entry_edges(graph, label, entry, &first, fact.0),
+            self.1
+                .analyze_entry_edges(graph, label, entry, &second, fact.1),
+        )
+    }
+
+    fn analyze_instruction(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        instruction: &L::Instruction,
+        analyze: AnalyzeInstruction<(FA, FB)>,
+    ) -> Option<RewriteInstruction<L>> {
+        let (fact, mut budget) = analyze.into_parts();
+
+        // If the second one rewrites, the first one's half has to go back to how it was, since
+        //   it'll be analyzing the new code from the same place. A rewrite that wasn't allowed
+        //   gets handed back all the same, for the engine to catch.
+        let first = fact.0.clone();
+        let result = self.0.analyze_instruction(
+            graph,
+            label,
+            instruction,
+            AnalyzeInstruction::new(&mut fact.0, budget.reborrow()),
+        );
+        if result.is_some() {
+            return result;
+        }
+
+        let result = self.1.analyze_instruction(
+            graph,
+            label,
+            instruction,
+            AnalyzeInstruction::new(&mut fact.1, budget),
+        );
+        if result.is_some() {
+            fact.0 = first;
+            return result;
+        }
+        None
+    }
+
+    fn analyze_exit(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        exit: &L::Exit,
+        analyze: AnalyzeExit<(FA, FB)>,
+    ) -> RewriteExit<L, (FA, FB)> {
+        let (fact, mut budget) = analyze.into_parts();
+
+        let first = match exit_facts(self.0.analyze_exit(
+            graph,
+            label,
+            exit,
+            AnalyzeExit::new(&fact.0, budget.reborrow()),
+        )) {
+            Ok(facts) => facts,
+            Err(result) => return result,
+        };
+        let second = match exit_facts(self.1.analyze_exit(
+            graph,
+            label,
+            exit,
+            AnalyzeExit::new(&fact.1, budget),
+        )) {
+            Ok(facts) => facts,
+            Err(result) => return result,
+        };
+        RewriteExit::Done(zip_facts(first, second))
+    }
+
+    fn rewrite_depth(&self) -> RewriteDepth {
+        self.0.rewrite_depth().shallower(self.1.rewrite_depth())
+    }
+
+    fn analyze_edge(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        exit: &L::Exit,
+        successor: &L::Entry,
+        fact: (FA, FB),
+    ) -> (FA, FB) {
+        (
+            self.0.analyze_edge(graph, label, exit, successor, fact.0),
+            self.1.analyze_edge(graph, label, exit, successor, fact.1),
+        )
+    }
+}
+
+impl<L, A, B, FA, FB> BackwardAnalysis<L, (FA, FB)> for Pair<A, B>
+where
+    L: Language,
+    A: BackwardAnalysis<L, FA>,
+    B: BackwardAnalysis<L, FB>,
+    FA: Lattice,
+    FB: Lattice,
+{
+    fn analyze_exit(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        exit: &L::Exit,
+        analyze: AnalyzeExitBackward<(FA, FB)>,
+    ) -> Option<RewriteExitBackward<L>> {
+        let (fact, mut budget) = analyze.into_parts();
+
+        let first = fact.0.clone();
+        let result = self.0.analyze_exit(
+            graph,
+            label,
+            exit,
+            AnalyzeExitBackward::new(&mut fact.0, budget.reborrow()),
+        );
+        if result.is_some() {
+            return result;
+        }
+
+        let result = self.1.analyze_exit(
+            graph,
+            label,
+            exit,
+            AnalyzeExitBackward::new(&mut fact.1, budget),
+        );
+        if result.is_some() {
+            fact.0 = first;
+            return result;
+        }
+        None
+    }
+
+    fn analyze_instruction(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        instruction: &L::Instruction,
+        analyze: AnalyzeInstructionBackward<(FA, FB)>,
+    ) -> Option<RewriteInstructionBackward<L>> {
+        let (fact, mut budget) = analyze.into_parts();
+
+        let first = fact.0.clone();
+        let result = self.0.analyze_instruction(
+            graph,
+            label,
+            instruction,
+            AnalyzeInstructionBackward::new(&mut fact.0, budget.reborrow()),
+        );
+        if result.is_some() {
+            return result;
+        }
+
+        let result = self.1.analyze_instruction(
+            graph,
+            label,
+            instruction,
+            AnalyzeInstructionBackward::new(&mut fact.1, budget),
+        );
+        if result.is_some() {
+            fact.0 = first;
+            return result;
+        }
+        None
+    }
+
+    fn analyze_entry(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        entry: &L::Entry,
+        fact: (FA, FB),
+    ) -> FactBase<(FA, FB)> {
+        zip_facts(
+            self.0.analyze_entry(graph, label, entry, fact.0),
+            self.1.analyze_entry(graph, label, entry, fact.1),
+        )
+    }
+
+    fn rewrite_depth(&self) -> RewriteDepth {
+        self.0.rewrite_depth().shallower(self.1.rewrite_depth())
+    }
+}
+
+impl<L, A, B, F> ForwardAnalysis<L, F> for OrElse<A, B>
+where
+    L: Language,
+    A: ForwardAnalysis<L, F>,
+    B: ForwardAnalysis<L, F>,
+    F: Lattice,
+{
+    fn analyze_entry(&mut self, graph: &Graph<L>, label: Label, entry: &L::Entry, fact: F) -> F {
+        self.0.analyze_entry(graph, label, entry, fact)
+    }
+
+    fn analyze_entry_edges(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        entry: &L::Entry,
+        incoming: &FactBase<F>,
+        fact: F,
+    ) -> F {
+        self.0
+            .analyze_entry_edges(graph, label, entry, incoming, fact)
+    }
+
+    fn analyze_instruction(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        instruction: &L::Instruction,
+        analyze: AnalyzeInstruction<F>,
+    ) -> Option<RewriteInstruction<L>> {
+        let (fact, mut budget) = analyze.into_parts();
+        let before = if budget.can_rewrite() {
+            Some(fact.clone())
+        } else {
+            None
+        };
+
+        let result = self.0.analyze_instruction(
+            graph,
+            label,
+            instruction,
+            AnalyzeInstruction::new(&mut *fact, budget.reborrow()),
+        );
+        match (result, before) {
+            (Some(result), _) => Some(result),
+            (None, Some(before)) => {
+                or_else_instruction(&mut self.1, graph, label, instruction, fact, before, budget)
+            }
+            (None, None) => None,
+        }
+    }
+
+    fn analyze_exit(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        exit: &L::Exit,
+        analyze: AnalyzeExit<F>,
+    ) -> RewriteExit<L, F> {
+        let (fact, mut budget) = analyze.into_parts();
+        let rewrite = budget.can_rewrite();
+
+        let result = self.0.analyze_exit(
+            graph,
+            label,
+            exit,
+            AnalyzeExit::new(fact, budget.reborrow()),
+        );
+        match result {
+            RewriteExit::Done(facts) if rewrite => {
+                or_else_exit(&mut self.1, graph, label, exit, fact, facts, budget)
+            }
+            result => result,
+        }
+    }
+
+    fn rewrite_depth(&self) -> RewriteDepth {
+        self.0.rewrite_depth().shallower(self.1.rewrite_depth())
+    }
+
+    fn analyze_edge(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        exit: &L::Exit,
+        successor: &L::Entry,
+        fact: F,
+    ) -> F {
+        self.0.analyze_edge(graph, label, exit, successor, fact)
+    }
+}
+
+impl<L, A, B, F> BackwardAnalysis<L, F> for OrElse<A, B>
+where
+    L: Language,
+    A: BackwardAnalysis<L, F>,
+    B: BackwardAnalysis<L, F>,
+    F: Lattice,
+{
+    fn analyze_exit(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        exit: &L::Exit,
+        analyze: AnalyzeExitBackward<F>,
+    ) -> Option<RewriteExitBackward<L>> {
+        let (fact, mut budget) = analyze.into_parts();
+        let before = if budget.can_rewrite() {
+            Some(fact.clone())
+        } else {
+            None
+        };
+
+        let result = self.0.analyze_exit(
+            graph,
+            label,
+            exit,
+            AnalyzeExitBackward::new(&mut *fact, budget.reborrow()),
+        );
+        match (result, before) {
+            (Some(result), _) => Some(result),
+            (None, Some(before)) => {
+                or_else_exit_backward(&mut self.1, graph, label, exit, fact, before, budget)
+            }
+            (None, None) => None,
+        }
+    }
+
+    fn analyze_instruction(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        instruction: &L::Instruction,
+        analyze: AnalyzeInstructionBackward<F>,
+    ) -> Option<RewriteInstructionBackward<L>> {
+        let (fact, mut budget) = analyze.into_parts();
+        let before = if budget.can_rewrite() {
+            Some(fact.clone())
+        } else {
+            None
+        };
+
+        let result = self.0.analyze_instruction(
+            graph,
+            label,
+            instruction,
+            AnalyzeInstructionBackward::new(&mut *fact, budget.reborrow()),
+        );
+        match (result, before) {
+            (Some(result), _) => Some(result),
+            (None, Some(before)) => or_else_instruction_backward(
+                &mut self.1,
+                graph,
+                label,
+                instruction,
+                fact,
+                before,
+                budget,
+            ),
+            (None, None) => None,
+        }
+    }
+
+    fn analyze_entry(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        entry: &L::Entry,
+        fact: F,
+    ) -> FactBase<F> {
+        self.0.analyze_entry(graph, label, entry, fact)
+    }
+
+    fn rewrite_depth(&self) -> RewriteDepth {
+        self.0.rewrite_depth().shallower(self.1.rewrite_depth())
+    }
+}
+
+impl<L, A, B, F> ForwardAnalysis<L, F> for Then<A, B>
+where
+    L: Language,
+    A: ForwardAnalysis<L, F>,
+    B: ForwardAnalysis<L, F>,
+    F: Lattice,
+{
+    fn analyze_entry(&mut self, graph: &Graph<L>, label: Label, entry: &L::Entry, fact: F) -> F {
+        self.0.analyze_entry(graph, label, entry, fact)
+    }
+
+    fn analyze_entry_edges(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        entry: &L::Entry,
+        incoming: &FactBase<F>,
+        fact: F,
+    ) -> F {
+        self.0
+            .analyze_entry_edges(graph, label, entry, incoming, fact)
+    }
+
+    fn analyze_instruction(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        instruction: &L::Instruction,
+        analyze: AnalyzeInstruction<F>,
+    ) -> Option<RewriteInstruction<L>> {
+        let rewrite_depth = self.rewrite_depth();
+        let (fact, mut budget) = analyze.into_parts();
+        let before = if budget.can_rewrite() {
+            Some(fact.clone())
+        } else {
+            None
+        };
+
+        let result = self.0.analyze_instruction(
+            graph,
+            label,
+            instruction,
+            AnalyzeInstruction::new(&mut *fact, budget.reborrow()),
+        );
+        match (result, before) {
+            (Some(result), Some(before)) => Some(then_instruction(
+                &mut self.1,
+                graph,
+                label,
+                result,
+                before,
+                budget.nested(rewrite_depth),
+            )),
+            (Some(result), None) => Some(result),
+            (None, Some(before)) => {
+                or_else_instruction(&mut self.1, graph, label, instruction, fact, before, budget)
+            }
+            (None, None) => None,
+        }
+    }
+
+    fn analyze_exit(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        exit: &L::Exit,
+        analyze: AnalyzeExit<F>,
+    ) -> RewriteExit<L, F> {
+        let rewrite_depth = self.rewrite_depth();
+        let (fact, mut budget) = analyze.into_parts();
+        let rewrite = budget.can_rewrite();
+
+        let result = self.0.analyze_exit(
+            graph,
+            label,
+            exit,
+            AnalyzeExit::new(fact, budget.reborrow()),
+        );
+        if !rewrite {
+            return result;
+        }
+        match result {
+            RewriteExit::Done(facts) => {
+                or_else_exit(&mut self.1, graph, label, exit, fact, facts, budget)
+            }
+            result => then_exit(
+                &mut self.1,
+                graph,
+                label,
+                result,
+                fact.clone(),
+                budget.nested(rewrite_depth),
+            ),
+        }
+    }
+
+    fn rewrite_depth(&self) -> RewriteDepth {
+        self.0.rewrite_depth().shallower(self.1.rewrite_depth())
+    }
+
+    fn analyze_edge(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        exit: &L::Exit,
+        successor: &L::Entry,
+        fact: F,
+    ) -> F {
+        self.0.analyze_edge(graph, label, exit, successor, fact)
+    }
+}
+
+impl<L, A, B, F> BackwardAnalysis<L, F> for Then<A, B>
+where
+    L: Language,
+    A: BackwardAnalysis<L, F>,
+    B: BackwardAnalysis<L, F>,
+    F: Lattice,
+{
+    fn analyze_exit(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        exit: &L::Exit,
+        analyze: AnalyzeExitBackward<F>,
+    ) -> Option<RewriteExitBackward<L>> {
+        let rewrite_depth = self.rewrite_depth();
+        let (fact, mut budget) = analyze.into_parts();
+        let before = if budget.can_rewrite() {
+            Some(fact.clone())
+        } else {
+            None
+        };
+
+        let result = self.0.analyze_exit(
+            graph,
+            label,
+            exit,
+            AnalyzeExitBackward::new(&mut *fact, budget.reborrow()),
+        );
+        match (result, before) {
+            (Some(result), Some(before)) => Some(then_exit_backward(
+                &mut self.1,
+                graph,
+                label,
+                result,
+                before,
+                budget.nested(rewrite_depth),
+            )),
+            (Some(result), None) => Some(result),
+            (None, Some(before)) => {
+                or_else_exit_backward(&mut self.1, graph, label, exit, fact, before, budget)
+            }
+            (None, None) => None,
+        }
+    }
+
+    fn analyze_instruction(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        instruction: &L::Instruction,
+        analyze: AnalyzeInstructionBackward<F>,
+    ) -> Option<RewriteInstructionBackward<L>> {
+        let rewrite_depth = self.rewrite_depth();
+        let (fact, mut budget) = analyze.into_parts();
+        let before = if budget.can_rewrite() {
+            Some(fact.clone())
+        } else {
+            None
+        };
+
+        let result = self.0.analyze_instruction(
+            graph,
+            label,
+            instruction,
+            AnalyzeInstructionBackward::new(&mut *fact, budget.reborrow()),
+        );
+        match (result, before) {
+            (Some(result), Some(before)) => Some(then_instruction_backward(
+                &mut self.1,
+                graph,
+                label,
+                result,
+                before,
+                budget.nested(rewrite_depth),
+            )),
+            (Some(result), None) => Some(result),
+            (None, Some(before)) => or_else_instruction_backward(
+                &mut self.1,
+                graph,
+                label,
+                instruction,
+                fact,
+                before,
+                budget,
+            ),
+            (None, None) => None,
+        }
+    }
+
+    fn analyze_entry(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        entry: &L::Entry,
+        fact: F,
+    ) -> FactBase<F> {
+        self.0.analyze_entry(graph, label, entry, fact)
+    }
+
+    fn rewrite_depth(&self) -> RewriteDepth {
+        self.0.rewrite_depth().shallower(self.1.rewrite_depth())
+    }
+}
+
+// The first analysis has already moved 'fact' past the instruction, so the second one looks at
+//   it from 'before'. If it does rewrite, the fact goes back to 'before' so that the new code
+//   gets analyzed from the right place.
+fn or_else_instruction<L, B, F>(
+    second: &mut B,
+    graph: &Graph<L>,
+    label: Label,
+    instruction: &L::Instruction,
+    fact: &mut F,
+    mut before: F,
+    budget: Budget,
+) -> Option<RewriteInstruction<L>>
+where
+    L: Language,
+    B: ForwardAnalysis<L, F>,
+{
+    let result = second.analyze_instruction(
+        graph,
+        label,
+        instruction,
+        AnalyzeInstruction::new(&mut before, budget),
+    )?;
+    *fact = before;
+    Some(result)
+}
+
+fn or_else_exit<L, B, F>(
+    second: &mut B,
+    graph: &Graph<L>,
+    label: Label,
+    exit: &L::Exit,
+    fact: &F,
+    facts: FactBase<F>,
+    budget: Budget,
+) -> RewriteExit<L, F>
+where
+    L: Language,
+    B: ForwardAnalysis<L, F>,
+{
+    match second.analyze_exit(graph, label, exit, AnalyzeExit::new(fact, budget)) {
+        RewriteExit::Done(_) => RewriteExit::Done(facts),
+        result => result,
+    }
+}
+
+fn or_else_instruction_backward<L, B, F>(
+    second: &mut B,
+    graph: &Graph<L>,
+    label: Label,
+    instruction: &L::Instruction,
+    fact: &mut F,
+    mut before: F,
+    budget: Budget,
+) -> Option<RewriteInstructionBackward<L>>
+where
+    L: Language,
+    B: BackwardAnalysis<L, F>,
+{
+    let result = second.analyze_instruction(
+        graph,
+        label,
+        instruction,
+        AnalyzeInstructionBackward::new(&mut before, budget),
+    )?;
+    *fact = before;
+    Some(result)
+}
+
+fn or_else_exit_backward<L, B, F>(
+    second: &mut B,
+    graph: &Graph<L>,
+    label: Label,
+    exit: &L::Exit,
+    fact: &mut F,
+    mut before: F,
+    budget: Budget,
+) -> Option<RewriteExitBackward<L>>
+where
+    L: Language,
+    B: BackwardAnalysis<L, F>,
+{
+    let result = second.analyze_exit(
+        graph,
+        label,
+        exit,
+        AnalyzeExitBackward::new(&mut before, budget),
+    )?;
+    *fact = before;
+    Some(result)
+}
+
+// Hands the instructions the first analysis rewrote to into the second one, starting from the
+//   fact the first one saw. Rewriting into nothing counts as having been handed on too.
+fn then_instruction<L, B, F>(
+    second: &mut B,
+    graph: &Graph<L>,
+    label: Label,
+    rewrite: RewriteInstruction<L>,
+    mut fact: F,
+    mut budget: Budget,
+) -> RewriteInstruction<L>
+where
+    L: Language,
+    B: ForwardAnalysis<L, F>,
+{
+    let instructions = match rewrite.0 {
+        forward::RewriteInstructionEnum::Single(instruction) => {
+            // A single instruction can become anything at all, even a sub-graph.
+            let rewrite = budget.can_rewrite();
+            let result = second.analyze_instruction(
+                graph,
+                label,
+                &instruction,
+                AnalyzeInstruction::new(&mut fact, budget.reborrow()),
+            );
+            return match result {
+                Some(result) => {
+                    assert!(
+                        rewrite,
+                        "Instructions can only be rewritten when can_rewrite() allows it"
+                    );
+                    budget.burn();
+                    result
+                }
+                None => RewriteInstruction(forward::RewriteInstructionEnum::Single(instruction)),
+            };
+        }
+        forward::RewriteInstructionEnum::Multiple(instructions) => instructions,
+        graph_rewrite => return RewriteInstruction(graph_rewrite),
+    };
+
+    let output = then_instructions(second, graph, label, instructions, &mut fact, budget);
+    RewriteInstruction(forward::RewriteInstructionEnum::Multiple(output))
+}
+
+// Hands a list of instructions into the second analysis, one after the other, moving 'fact'
+//   past each of them. A sub-graph can't go in the middle of a list of instructions, so those
+//   rewrites get left alone.
+fn then_instructions<L, B, F>(
+    second: &mut B,
+    graph: &Graph<L>,
+    label: Label,
+    instructions: Vec<L::Instruction>,
+    fact: &mut F,
+    mut budget: Budget,
+) -> Vec<L::Instruction>
+where
+    L: Language,
+    B: ForwardAnalysis<L, F>,
+{
+    let mut output = vec![];
+    for instruction in instructions {
+        let rewrite = budget.can_rewrite();
+        let result = second.analyze_instruction(
+            graph,
+            label,
+            &instruction,
+            AnalyzeInstruction::new(&mut *fact, budget.reborrow()),
+        );
+        if result.is_some() {
+            assert!(
+                rewrite,
+                "Instructions can only be rewritten when can_rewrite() allows it"
+            );
+        }
+        let rewritten = match result.map(|result| result.0) {
+            None => {
+                output.push(instruction);
+                continue;
+            }
+            Some(forward::RewriteInstructionEnum::Graph(..)) => vec![instruction],
+            Some(forward::RewriteInstructionEnum::Single(instruction)) => {
+                budget.burn();
+                vec![instruction]
+            }
+            Some(forward::RewriteInstructionEnum::Multiple(instructions)) => {
+                budget.burn();
+                instructions
+            }
+        };
+        for instruction in rewritten {
+            second.analyze_instruction(
+                graph,
+                label,
+                &instruction,
+                AnalyzeInstruction::new(&mut *fact, Budget::none()),
+            );
+            output.push(instruction);
+        }
+    }
+    output
+}
+
+// Hands the exit the first analysis rewrote to into the second one, after any instructions that
+//   came with it. Only an exit on its own can become a sub-graph, just like with instructions.
+fn then_exit<L, B, F>(
+    second: &mut B,
+    graph: &Graph<L>,
+    label: Label,
+    rewrite: RewriteExit<L, F>,
+    mut fact: F,
+    mut budget: Budget,
+) -> RewriteExit<L, F>
+where
+    L: Language,
+    B: ForwardAnalysis<L, F>,
+{
+    let (instructions, exit) = match rewrite {
+        RewriteExit::Single(exit) => (vec![], exit),
+        RewriteExit::Extend(instructions, exit) => (instructions, exit),
+        result => return result,
+    };
+    let mut instructions = then_instructions(
+        second,
+        graph,
+        label,
+        instructions,
+        &mut fact,
+        budget.reborrow(),
+    );
+
+    let rewrite = budget.can_rewrite();
+    let result = second.analyze_exit(
+        graph,
+        label,
+        &exit,
+        AnalyzeExit::new(&fact, budget.reborrow()),
+    );
+    let exit = match result {
+        RewriteExit::Done(_) => exit,
+        RewriteExit::Graph(..) if !instructions.is_empty() => exit,
+        result => {
+            assert!(
+                rewrite,
+                "Exits can only be rewritten when can_rewrite() allows it"
+            );
+            budget.burn();
+            match result {
+                RewriteExit::Single(exit) => exit,
+                RewriteExit::Extend(more, exit) => {
+                    instructions.extend(more);
+                    exit
+                }
+                result => return result,
+            }
+        }
+    };
+
+    if instructions.is_empty() {
+        RewriteExit::Single(exit)
+    } else {
+        RewriteExit::Extend(instructions, exit)
+    }
+}
+
+// The same as then_instruction, only the facts flow from the last instruction to the first.
+fn then_instruction_backward<L, B, F>(
+    second: &mut B,
+    graph: &Graph<L>,
+    label: Label,
+    rewrite: RewriteInstructionBackward<L>,
+    mut fact: F,
+    mut budget: Budget,
+) -> RewriteInstructionBackward<L>
+where
+    L: Language,
+    B: BackwardAnalysis<L, F>,
+{
+    let instructions = match rewrite.0 {
+        backward::RewriteInstructionEnum::Single(instruction) => {
+            let rewrite = budget.can_rewrite();
+            let result = second.analyze_instruction(
+                graph,
+                label,
+                &instruction,
+                AnalyzeInstructionBackward::new(&mut fact, budget.reborrow()),
+            );
+            return match result {
+                Some(result) => {
+                    assert!(
+                        rewrite,
+                        "Instructions can only be rewritten when can_rewrite() allows it"
+                    );
+                    budget.burn();
+                    result
+                }
+                None => RewriteInstructionBackward(backward::RewriteInstructionEnum::Single(
+                    instruction,
+                )),
+            };
+        }
+        backward::RewriteInstructionEnum::Multiple(instructions) => instructions,
+        graph_rewrite => return RewriteInstructionBackward(graph_rewrite),
+    };
+
+    let output = then_instructions_backward(second, graph, label, instructions, &mut fact, budget);
+    RewriteInstructionBackward(backward::RewriteInstructionEnum::Multiple(output))
+}
+
+fn then_instructions_backward<L, B, F>(
+    second: &mut B,
+    graph: &Graph<L>,
+    label: Label,
+    instructions: Vec<L::Instruction>,
+    fact: &mut F,
+    mut budget: Budget,
+) -> Vec<L::Instruction>
+where
+    L: Language,
+    B: BackwardAnalysis<L, F>,
+{
+    let mut output = vec![];
+    for instruction in instructions.into_iter().rev() {
+        let rewrite = budget.can_rewrite();
+        let result = second.analyze_instruction(
+            graph,
+            label,
+            &instruction,
+            AnalyzeInstructionBackward::new(&mut *fact, budget.reborrow()),
+        );
+        if result.is_some() {
+            assert!(
+                rewrite,
+                "Instructions can only be rewritten when can_rewrite() allows it"
+            );
+        }
+        let rewritten = match result.map(|result| result.0) {
+            None => {
+                output.push(instruction);
+                continue;
+            }
+            Some(backward::RewriteInstructionEnum::Graph(..)) => vec![instruction],
+            Some(backward::RewriteInstructionEnum::Single(instruction)) => {
+                budget.burn();
+                vec![instruction]
+            }
+            Some(backward::RewriteInstructionEnum::Multiple(instructions)) => {
+                budget.burn();
+                instructions
+            }
+        };
+        for instruction in rewritten.into_iter().rev() {
+            second.analyze_instruction(
+                graph,
+                label,
+                &instruction,
+                AnalyzeInstructionBackward::new(&mut *fact, Budget::none()),
+            );
+            output.push(instruction);
+        }
+    }
+    output.reverse();
+    output
+}
+
+// The same as then_exit, only the new exit gets handed on before the instructions that came
+//   with it.
+fn then_exit_backward<L, B, F>(
+    second: &mut B,
+    graph: &Graph<L>,
+    label: Label,
+    rewrite: RewriteExitBackward<L>,
+    mut fact: F,
+    mut budget: Budget,
+) -> RewriteExitBackward<L>
+where
+    L: Language,
+    B: BackwardAnalysis<L, F>,
+{
+    let (instructions, exit) = match rewrite.0 {
+        backward::RewriteExitEnum::Single(exit) => (vec![], exit),
+        backward::RewriteExitEnum::Extend(instructions, exit) => (instructions, exit),
+        graph_rewrite => return RewriteExitBackward(graph_rewrite),
+    };
+
+    let rewrite = budget.can_rewrite();
+    let result = second.analyze_exit(
+        graph,
+        label,
+        &exit,
+        AnalyzeExitBackward::new(&mut fact, budget.reborrow()),
+    );
+    let (exit, more) = match result.map(|result| result.0) {
+        None => (exit, vec![]),
+        Some(backward::RewriteExitEnum::Graph(..)) if !instructions.is_empty() => {
+            second.analyze_exit(
+                graph,
+                label,
+                &exit,
+                AnalyzeExitBackward::new(&mut fact, Budget::none()),
+            );
+            (exit, vec![])
+        }
+        Some(result) => {
+            assert!(
+                rewrite,
+                "Exits can only be rewritten when can_rewrite() allows it"
+            );
+            budget.burn();
+            let (more, exit) = match result {
+                backward::RewriteExitEnum::Single(exit) => (vec![], exit),
+                backward::RewriteExitEnum::Extend(more, exit) => (more, exit),
+                graph_rewrite => return RewriteExitBackward(graph_rewrite),
+            };
+            second.analyze_exit(
+                graph,
+                label,
+                &exit,
+                AnalyzeExitBackward::new(&mut fact, Budget::none()),
+            );
+            for instruction in more.iter().rev() {
+                second.analyze_instruction(
+                    graph,
+                    label,
+                    instruction,
+                    AnalyzeInstructionBackward::new(&mut fact, Budget::none()),
+                );
+            }
+            (exit, more)
+        }
+    };
+
+    let mut instructions =
+        then_instructions_backward(second, graph, label, instructions, &mut fact, budget);
+    instructions.extend(more);
+    if instructions.is_empty() {
+        RewriteExitBackward(backward::RewriteExitEnum::Single(exit))
+    } else {
+        RewriteExitBackward(backward::RewriteExitEnum::Extend(instructions, exit))
+    }
+}
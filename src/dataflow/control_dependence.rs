@@ -0,0 +1,82 @@
+use fnv::FnvHashMap;
+
+use super::dominator::PostDominatorTree;
+use super::graph::{Graph, Label, Language};
+
+// Which edges out of a branch decide whether each block runs. A block is control dependent on
+//   the edge from 'branch' to 'successor' when it post-dominates 'successor' (or is it) but
+//   doesn't strictly post-dominate 'branch', so taking that edge means it's going to run and
+//   taking another one might mean it won't.
+//
+// Only the blocks in the post-dominator tree are looked at. A loop header is control
+//   dependent on the edge back into itself.
+#[derive(Clone, Debug)]
+pub struct ControlDependence {
+    dependences: FnvHashMap<Label, Vec<(Label, Label)>>,
+    dependents: FnvHashMap<(Label, Label), Vec<Label>>,
+}
+
+impl ControlDependence {
+    pub fn new<L: Language>(
+        graph: &Graph<L>,
+        post_dominators: &PostDominatorTree,
+    ) -> ControlDependence {
+        let mut control = ControlDependence {
+            dependences: FnvHashMap::default(),
+            dependents: FnvHashMap::default(),
+        };
+
+        for branch in graph.labels() {
+            if !post_dominators.contains(branch) {
+                continue;
+            }
+            let mut successors = graph[branch].successors();
+            successors.sort_by_key(|successor| successor.0);
+            successors.dedup();
+
+            // Everything on the way up the tree from the successor is dependent on the edge,
+            //   until we get to whatever post-dominates the branch.
+            let stop = post_dominators.ipdom(branch);
+            for successor in successors {
+                let mut runner = Some(successor);
+                while let Some(current) = runner {
+                    if Some(current) == stop {
+                        break;
+                    }
+                    control
+                        .dependences
+                        .entry(current)
+                        .or_default()
+                        .push((branch, successor));
+                    control
+                        .dependents
+                        .entry((branch, successor))
+                        .or_default()
+                        .push(current);
+                    runner = post_dominators.ipdom(current);
+                }
+            }
+        }
+
+        for dependences in control.dependences.values_mut() {
+            dependences.sort_by_key(|(branch, successor)| (branch.0, successor.0));
+        }
+        for dependents in control.dependents.values_mut() {
+            dependents.sort_by_key(|label| label.0);
+        }
+        control
+    }
+
+    // The edges this block is control dependent on, as (branch, successor) pairs in label
+    //   order. Blocks that run whenever the graph does aren't dependent on anything.
+    pub fn dependences(&self, label: Label) -> &[(Label, Label)] {
+        self.dependences.get(&label).map_or(&[], Vec::as_slice)
+    }
+
+    // The blocks that are control dependent on the edge, in label order.
+    pub fn dependents(&self, branch: Label, successor: Label) -> &[Label] {
+        self.dependents
+            .get(&(branch, successor))
+            .map_or(&[], Vec::as_slice)
+    }
+}
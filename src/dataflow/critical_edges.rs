@@ -0,0 +1,47 @@
+use super::graph::{BasicBlock, ControlFlow, Exit, Graph, Label, Language};
+
+// An edge is critical when the block it comes from can go somewhere else, and the block it
+//   goes to can be come from somewhere else. Nothing can be put on an edge like that without
+//   it also happening on some other edge, unless the edge gets its own block. There's no edge
+//   at all from a block that isn't in the graph.
+pub fn is_critical_edge<L: Language>(graph: &Graph<L>, from: Label, to: Label) -> bool {
+    let block = match graph.get(from) {
+        Some(block) => block,
+        None => return false,
+    };
+    let mut successors = block.successors();
+    successors.sort_by_key(|successor| successor.0);
+    successors.dedup();
+    successors.len() > 1 && successors.contains(&to) && graph.predecessors(to).len() > 1
+}
+
+// Puts a new block on every critical edge, which just goes on to where the edge used to go,
+//   passing along anything the edge did. New blocks get their labels from the graph's label
+//   supply.
+pub fn split_critical_edges<L: ControlFlow>(graph: &Graph<L>) -> Graph<L> {
+    let mut labels: Vec<Label> = graph.labels().collect();
+    labels.sort_by_key(|label| label.0);
+
+    let mut blocks = vec![];
+    for label in labels {
+        let block = &graph[label];
+        let mut exit = block.exit.clone();
+        for successor in block.successors() {
+            // An exit can go to the same place more than once, which is still only one edge,
+            //   and once it's been split the exit doesn't go there any more.
+            if exit.successors().contains(&successor) && is_critical_edge(graph, label, successor) {
+                let split = graph.fresh_label();
+                let (new_exit, split_exit) = L::split_edge(&exit, successor, split);
+                exit = new_exit;
+                blocks.push(BasicBlock::new(L::label_entry(split), vec![], split_exit));
+            }
+        }
+        blocks.push(BasicBlock::new(
+            block.entry.clone(),
+            block.code.clone(),
+            exit,
+        ));
+    }
+
+    Graph::from_blocks(blocks)
+}
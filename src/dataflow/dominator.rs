@@ -1,7 +1,7 @@
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
 
 use super::forward_analysis::*;
-use super::graph::{Entry, Graph, Label, Language};
+use super::graph::{Entry, Graph, GraphError, Label, Language};
 use super::lattice::Lattice;
 
 #[derive(Clone, Debug)]
@@ -14,7 +14,7 @@ impl Lattice for DominatorFact {
         DominatorFact { dominates: None }
     }
 
-    fn join(&mut self, other: &Self, label: Label) -> bool {
+    fn join(&mut self, other: &Self, _label: Label) -> bool {
         if self.dominates.is_none() {
             self.dominates = other.dominates.clone();
             return true;
@@ -41,7 +41,7 @@ impl<L: Language> ForwardAnalysis<L, DominatorFact> for DominatorAnalysis {
     fn analyze_entry(
         &mut self,
         _graph: &Graph<L>,
-        label: Label,
+        _label: Label,
         entry: &L::Entry,
         mut fact: DominatorFact,
     ) -> DominatorFact {
@@ -64,8 +64,386 @@ impl<L: Language> ForwardAnalysis<L, DominatorFact> for DominatorAnalysis {
         _graph: &Graph<L>,
         _label: Label,
         exit: &L::Exit,
-        fact: &DominatorFact,
+        analyze: AnalyzeExit<DominatorFact>,
     ) -> RewriteExit<L, DominatorFact> {
-        RewriteExit::Done(distribute_facts::<L, DominatorFact>(exit, fact))
+        RewriteExit::Done(distribute_facts::<L, DominatorFact>(exit, analyze.fact()))
+    }
+}
+
+// The dominator tree of the blocks reachable from an entry, built with the algorithm from
+//   Cooper, Harvey & Kennedy's "A Simple, Fast Dominance Algorithm". Blocks that can't be
+//   reached from the entry aren't in the tree at all.
+#[derive(Clone, Debug)]
+pub struct DominatorTree {
+    entry: Label,
+    tree: Tree,
+    frontiers: FnvHashMap<Label, Vec<Label>>,
+}
+
+impl DominatorTree {
+    // Panics if the entry isn't in the graph. Use try_new to find out about that instead.
+    pub fn new<L: Language>(graph: &Graph<L>, entry: Label) -> DominatorTree {
+        DominatorTree::try_new(graph, entry).expect("The entry has to be in the graph")
+    }
+
+    pub fn try_new<L: Language>(
+        graph: &Graph<L>,
+        entry: Label,
+    ) -> Result<DominatorTree, GraphError> {
+        if !graph.contains(entry) {
+            return Err(GraphError::MissingEntry(entry));
+        }
+
+        let mut order = graph.post_order_traversal(entry);
+        order.reverse();
+
+        let index: FnvHashMap<Label, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(index, label)| (*label, index))
+            .collect();
+        let predecessors: Vec<Vec<usize>> = order
+            .iter()
+            .map(|label| {
+                graph
+                    .predecessors(*label)
+                    .iter()
+                    .filter_map(|predecessor| index.get(predecessor).cloned())
+                    .collect()
+            })
+            .collect();
+
+        let idoms = immediate_dominators(&predecessors);
+        let tree = Tree::new(
+            order
+                .iter()
+                .zip(idoms)
+                .enumerate()
+                .map(|(index, (label, idom))| (*label, Some(order[idom]).filter(|_| index > 0))),
+        );
+        let mut dominators = DominatorTree {
+            entry,
+            tree,
+            frontiers: FnvHashMap::default(),
+        };
+
+        // A block is in the frontier of everything on the way up the tree from each of its
+        //   predecessors, until we get to something that strictly dominates it.
+        for (label, predecessors) in order.iter().zip(&predecessors) {
+            for predecessor in predecessors {
+                let mut runner = Some(order[*predecessor]);
+                while let Some(current) = runner {
+                    if dominators.strictly_dominates(current, *label) {
+                        break;
+                    }
+                    let frontier = dominators.frontiers.entry(current).or_default();
+                    if !frontier.contains(label) {
+                        frontier.push(*label);
+                    }
+                    runner = dominators.idom(current);
+                }
+            }
+        }
+        for frontier in dominators.frontiers.values_mut() {
+            frontier.sort_by_key(|label| label.0);
+        }
+
+        Ok(dominators)
+    }
+
+    pub fn entry(&self) -> Label {
+        self.entry
+    }
+
+    // Whether the block was reachable from the entry, and so is in the tree.
+    pub fn contains(&self, label: Label) -> bool {
+        self.tree.contains(label)
+    }
+
+    // The closest block that dominates this one, other than itself. The entry doesn't have one,
+    //   and neither do blocks that aren't in the tree.
+    pub fn idom(&self, label: Label) -> Option<Label> {
+        self.tree.parent(label)
+    }
+
+    // Every block dominates itself. Nothing dominates, or is dominated by, a block that isn't
+    //   in the tree.
+    pub fn dominates(&self, a: Label, b: Label) -> bool {
+        self.tree.is_ancestor(a, b)
+    }
+
+    pub fn strictly_dominates(&self, a: Label, b: Label) -> bool {
+        a != b && self.dominates(a, b)
+    }
+
+    // The blocks this one is the immediate dominator of, in reverse post order.
+    pub fn children(&self, label: Label) -> &[Label] {
+        self.tree.children(label)
+    }
+
+    // How far down the tree the block is, where the entry is at depth 0.
+    pub fn depth(&self, label: Label) -> Option<usize> {
+        self.tree.depth(label)
+    }
+
+    // The blocks where this block stops dominating: the ones it doesn't strictly dominate, but
+    //   does dominate one of the predecessors of. In label order.
+    pub fn dominance_frontier(&self, label: Label) -> &[Label] {
+        self.frontiers.get(&label).map_or(&[], Vec::as_slice)
+    }
+
+    // The dominance frontier of all of the blocks, and then of everything in that, and so on
+    //   until nothing new turns up. For a set of blocks that assign to a variable, this is where
+    //   its phis go. In label order.
+    pub fn iterated_dominance_frontier(&self, labels: &[Label]) -> Vec<Label> {
+        let mut frontier = FnvHashSet::default();
+        let mut to_visit = labels.to_vec();
+        while let Some(label) = to_visit.pop() {
+            for &next in self.dominance_frontier(label) {
+                if frontier.insert(next) {
+                    to_visit.push(next);
+                }
+            }
+        }
+
+        let mut frontier: Vec<Label> = frontier.into_iter().collect();
+        frontier.sort_by_key(|label| label.0);
+        frontier
+    }
+}
+
+// The post-dominator tree of the blocks reachable from an entry. Every block that can't jump
+//   anywhere is treated as jumping to a single virtual exit, which post-dominates everything.
+//   It isn't a block, so it doesn't show up in the tree, which makes this a forest of the
+//   blocks whose only post-dominator is the virtual exit.
+//
+// Blocks stuck in an infinite loop never reach an exit at all. Some block in each loop like
+//   that is picked to jump to the virtual exit as well, so that every block ends up in the tree.
+//
+// Jumps to blocks that aren't in the graph are left out, just like when walking it, so a block
+//   that only jumps to one of those ends up jumping to the virtual exit too.
+#[derive(Clone, Debug)]
+pub struct PostDominatorTree {
+    tree: Tree,
+}
+
+impl PostDominatorTree {
+    pub fn new<L: Language>(graph: &Graph<L>, entry: Label) -> PostDominatorTree {
+        let reachable = graph.post_order_traversal(entry);
+        let within: FnvHashSet<Label> = reachable.iter().cloned().collect();
+
+        let mut exits: Vec<Label> = reachable
+            .iter()
+            .filter(|label| graph[**label].successors().is_empty())
+            .cloned()
+            .collect();
+        let mut order = vec![];
+        let mut visited = FnvHashSet::default();
+        for exit in &exits {
+            reverse_post_order_from(graph, &within, &mut visited, &mut order, *exit);
+        }
+
+        // Whatever we haven't seen yet can't reach an exit. The first of those to finish going
+        //   forwards can only jump to blocks stuck in the same loop as it.
+        for label in &reachable {
+            if !visited.contains(label) {
+                exits.push(*label);
+                reverse_post_order_from(graph, &within, &mut visited, &mut order, *label);
+            }
+        }
+        order.reverse();
+
+        // The virtual exit is node 0, and every block is numbered one after its place in the
+        //   order.
+        let index: FnvHashMap<Label, usize> = order
+            .iter()
+            .enumerate()
+            .map(|(index, label)| (*label, index + 1))
+            .collect();
+        let exits: FnvHashSet<Label> = exits.into_iter().collect();
+        let mut predecessors = vec![vec![]];
+        for label in &order {
+            let mut successors: Vec<usize> = graph[*label]
+                .successors()
+                .iter()
+                .filter_map(|successor| index.get(successor).copied())
+                .collect();
+            if exits.contains(label) {
+                successors.push(0);
+            }
+            predecessors.push(successors);
+        }
+
+        let ipdoms = immediate_dominators(&predecessors);
+        let tree = Tree::new(
+            order
+                .iter()
+                .zip(ipdoms.into_iter().skip(1))
+                .map(|(label, ipdom)| (*label, ipdom.checked_sub(1).map(|ipdom| order[ipdom]))),
+        );
+        PostDominatorTree { tree }
+    }
+
+    // Whether the block was reachable from the entry, and so is in the tree.
+    pub fn contains(&self, label: Label) -> bool {
+        self.tree.contains(label)
+    }
+
+    // The closest block that post-dominates this one, other than itself. Blocks that are only
+    //   post-dominated by the virtual exit don't have one.
+    pub fn ipdom(&self, label: Label) -> Option<Label> {
+        self.tree.parent(label)
+    }
+
+    // Every block post-dominates itself.
+    pub fn post_dominates(&self, a: Label, b: Label) -> bool {
+        self.tree.is_ancestor(a, b)
+    }
+
+    pub fn strictly_post_dominates(&self, a: Label, b: Label) -> bool {
+        a != b && self.post_dominates(a, b)
+    }
+
+    // The blocks that are only post-dominated by the virtual exit.
+    pub fn roots(&self) -> &[Label] {
+        &self.tree.roots
+    }
+
+    // The blocks this one is the immediate post-dominator of.
+    pub fn children(&self, label: Label) -> &[Label] {
+        self.tree.children(label)
+    }
+
+    // How far down the tree the block is, where the roots are at depth 0.
+    pub fn depth(&self, label: Label) -> Option<usize> {
+        self.tree.depth(label)
+    }
+}
+
+fn reverse_post_order_from<L: Language>(
+    graph: &Graph<L>,
+    within: &FnvHashSet<Label>,
+    visited: &mut FnvHashSet<Label>,
+    output: &mut Vec<Label>,
+    label: Label,
+) {
+    if !within.contains(&label) || !visited.insert(label) {
+        return;
+    }
+    for predecessor in graph.predecessors(label) {
+        reverse_post_order_from(graph, within, visited, output, *predecessor);
+    }
+    output.push(label);
+}
+
+// A forest of blocks, each knowing its parent, its children and how deep it is.
+#[derive(Clone, Debug)]
+struct Tree {
+    parents: FnvHashMap<Label, Label>,
+    children: FnvHashMap<Label, Vec<Label>>,
+    depths: FnvHashMap<Label, usize>,
+    roots: Vec<Label>,
+}
+
+impl Tree {
+    // Every block has to come after its parent.
+    fn new(nodes: impl Iterator<Item = (Label, Option<Label>)>) -> Tree {
+        let mut tree = Tree {
+            parents: FnvHashMap::default(),
+            children: FnvHashMap::default(),
+            depths: FnvHashMap::default(),
+            roots: vec![],
+        };
+        for (label, parent) in nodes {
+            match parent {
+                Some(parent) => {
+                    let depth = tree.depths[&parent] + 1;
+                    tree.depths.insert(label, depth);
+                    tree.parents.insert(label, parent);
+                    tree.children.entry(parent).or_default().push(label);
+                }
+                None => {
+                    tree.depths.insert(label, 0);
+                    tree.roots.push(label);
+                }
+            }
+        }
+        tree
+    }
+
+    fn contains(&self, label: Label) -> bool {
+        self.depths.contains_key(&label)
+    }
+
+    fn parent(&self, label: Label) -> Option<Label> {
+        self.parents.get(&label).cloned()
+    }
+
+    fn is_ancestor(&self, a: Label, mut b: Label) -> bool {
+        let (depth_a, mut depth_b) = match (self.depth(a), self.depth(b)) {
+            (Some(depth_a), Some(depth_b)) => (depth_a, depth_b),
+            _ => return false,
+        };
+        while depth_b > depth_a {
+            b = self.parents[&b];
+            depth_b -= 1;
+        }
+        a == b
+    }
+
+    fn children(&self, label: Label) -> &[Label] {
+        self.children.get(&label).map_or(&[], Vec::as_slice)
+    }
+
+    fn depth(&self, label: Label) -> Option<usize> {
+        self.depths.get(&label).cloned()
+    }
+}
+
+// The immediate dominator of every node in a graph given by the predecessors of each node,
+//   where the nodes are numbered in reverse post order from the root at 0. The root is its own
+//   immediate dominator.
+fn immediate_dominators(predecessors: &[Vec<usize>]) -> Vec<usize> {
+    let mut idoms: Vec<Option<usize>> = vec![None; predecessors.len()];
+    idoms[0] = Some(0);
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for node in 1..predecessors.len() {
+            let mut new_idom = None;
+            for &predecessor in &predecessors[node] {
+                if idoms[predecessor].is_none() {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => predecessor,
+                    Some(other) => intersect(&idoms, predecessor, other),
+                });
+            }
+            if new_idom.is_some() && idoms[node] != new_idom {
+                idoms[node] = new_idom;
+                changed = true;
+            }
+        }
+    }
+
+    idoms
+        .into_iter()
+        .map(|idom| idom.expect("Every node is reachable from the root"))
+        .collect()
+}
+
+// Walks both nodes up the tree until they meet. Nodes further down the tree always have a
+//   higher number than their dominators.
+fn intersect(idoms: &[Option<usize>], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while a > b {
+            a = idoms[a].expect("Only nodes with an idom are intersected");
+        }
+        while b > a {
+            b = idoms[b].expect("Only nodes with an idom are intersected");
+        }
     }
+    a
 }
@@ -1,22 +1,38 @@
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 
 use super::fact_base::FactBase;
-use super::graph::{Exit, Graph, Label, Language};
+use super::graph::{BasicBlock, Exit, Graph, Label, Language};
 use super::lattice::Lattice;
+use super::rewrite::{
+    assert_new_labels, burn, can_rewrite, BlockRewrite, Budget, Depths, Fuel, RewriteDepth,
+    Rewritten,
+};
 
 pub struct AnalyzeInstruction<'a, F> {
     fact: &'a mut F,
+    budget: Budget<'a>,
 }
 
 impl<'a, F> AnalyzeInstruction<'a, F> {
-    fn new(fact: &'a mut F) -> Self {
-        AnalyzeInstruction { fact }
+    pub(super) fn new(fact: &'a mut F, budget: Budget<'a>) -> Self {
+        AnalyzeInstruction { fact, budget }
+    }
+
+    pub(super) fn into_parts(self) -> (&'a mut F, Budget<'a>) {
+        (self.fact, self.budget)
     }
 
     pub fn fact(&self) -> &F {
         self.fact
     }
 
+    // Whether a rewrite would be accepted right now. When it wouldn't, say because we've run
+    //   out of fuel, the instruction is left as it is and gets analyzed again from the same
+    //   fact, which has to come back without a rewrite.
+    pub fn can_rewrite(&self) -> bool {
+        self.budget.can_rewrite()
+    }
+
     pub fn fact_mut(self) -> &'a mut F {
         self.fact
     }
@@ -32,6 +48,8 @@ impl<'a, F> AnalyzeInstruction<'a, F> {
         RewriteInstruction(RewriteInstructionEnum::Multiple(instructions))
     }
 
+    // The labels of the sub-graph, and of 'entry', can't already be in the graph. Taking them
+    //   from the graph's label supply, or renaming the sub-graph with it, makes sure of that.
     pub fn replace_with_graph<L: Language>(
         self,
         exit: L::Exit,
@@ -43,10 +61,10 @@ impl<'a, F> AnalyzeInstruction<'a, F> {
 }
 
 #[derive(Clone)]
-pub struct RewriteInstruction<L: Language>(RewriteInstructionEnum<L>);
+pub struct RewriteInstruction<L: Language>(pub(super) RewriteInstructionEnum<L>);
 
 #[derive(Clone)]
-enum RewriteInstructionEnum<L: Language> {
+pub(super) enum RewriteInstructionEnum<L: Language> {
     // Replace the currently analyzed instruction with this single, new instruction.
     Single(L::Instruction),
 
@@ -60,6 +78,31 @@ enum RewriteInstructionEnum<L: Language> {
     Graph(L::Exit, Graph<L>, L::Entry),
 }
 
+pub struct AnalyzeExit<'a, F> {
+    fact: &'a F,
+    budget: Budget<'a>,
+}
+
+impl<'a, F> AnalyzeExit<'a, F> {
+    pub(super) fn new(fact: &'a F, budget: Budget<'a>) -> Self {
+        AnalyzeExit { fact, budget }
+    }
+
+    pub(super) fn into_parts(self) -> (&'a F, Budget<'a>) {
+        (self.fact, self.budget)
+    }
+
+    pub fn fact(&self) -> &'a F {
+        self.fact
+    }
+
+    // Whether a rewrite would be accepted right now. When it wouldn't, the exit is left as it
+    //   is and gets analyzed again, which has to come back with RewriteExit::Done.
+    pub fn can_rewrite(&self) -> bool {
+        self.budget.can_rewrite()
+    }
+}
+
 pub enum RewriteExit<L: Language, F> {
     // The outgoing fact bases should be for the same labels as the same instruction we just analyzed.
     Done(FactBase<F>),
@@ -79,6 +122,21 @@ pub enum RewriteExit<L: Language, F> {
 pub trait ForwardAnalysis<L: Language, F> {
     fn analyze_entry(&mut self, graph: &Graph<L>, label: Label, entry: &L::Entry, fact: F) -> F;
 
+    // Used in place of analyze_entry, for analyses that need to see what each predecessor sent
+    //   in on its own, say to work out a phi. 'incoming' has the fact from every predecessor
+    //   analyzed so far, by its label, and 'fact' is all of them joined together, along with
+    //   the entry fact if this is where the graph starts.
+    fn analyze_entry_edges(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        entry: &L::Entry,
+        _incoming: &FactBase<F>,
+        fact: F,
+    ) -> F {
+        self.analyze_entry(graph, label, entry, fact)
+    }
+
     fn analyze_instruction(
         &mut self,
         graph: &Graph<L>,
@@ -92,8 +150,29 @@ pub trait ForwardAnalysis<L: Language, F> {
         graph: &Graph<L>,
         label: Label,
         exit: &L::Exit,
-        fact: &F,
+        analyze: AnalyzeExit<F>,
     ) -> RewriteExit<L, F>;
+
+    // Whether the code produced by a rewrite gets rewritten again. Shallow by default, so a
+    //   pass that keeps rewriting its own output can't go around forever.
+    fn rewrite_depth(&self) -> RewriteDepth {
+        RewriteDepth::Shallow
+    }
+
+    // The fact flowing along the edge from the block 'label', which ends in 'exit', to the
+    //   block starting with 'successor', before it gets joined with everything else flowing
+    //   into that block. This is the place to bind a block's parameters to the arguments its
+    //   predecessors pass it, see BlockParams::edge_bindings.
+    fn analyze_edge(
+        &mut self,
+        _graph: &Graph<L>,
+        _label: Label,
+        _exit: &L::Exit,
+        _successor: &L::Entry,
+        fact: F,
+    ) -> F {
+        fact
+    }
 }
 
 pub fn distribute_facts<L: Language, F: Clone>(exit: &L::Exit, fact: &F) -> FactBase<F> {
@@ -104,6 +183,9 @@ pub fn distribute_facts<L: Language, F: Clone>(exit: &L::Exit, fact: &F) -> Fact
     fact_base
 }
 
+// The facts at the fixed point, leaving the graph alone. Rewrites can still be made along the
+//   way to get better facts, but none of them are kept, and fuel only counts the ones that are,
+//   so there isn't any to pass in here.
 pub fn forward_analysis<L, A, F>(
     analysis: &mut A,
     graph: &Graph<L>,
@@ -115,38 +197,115 @@ where
     A: ForwardAnalysis<L, F>,
     F: Lattice,
 {
-    let mut fact_base = FnvHashMap::default();
-    fact_base.insert(entry, entry_fact);
+    let mut facts = ForwardFacts::new(entry, entry_fact);
 
-    fixed_point_forward_graph(analysis, &graph, entry, &mut fact_base);
+    let region = graph.labels().collect();
+    let depths = FnvHashMap::default();
+    fixed_point_forward_graph(analysis, graph, &region, &depths, &[entry], &mut facts);
 
-    fact_base
+    facts.joined
 }
 
-fn fixed_point_forward_graph<L, A, F>(
+// Like forward_analysis, but also hands back the graph with the rewrites applied.
+//
+// Rewrites made while the facts are still changing are only used to work out better facts,
+//   and get thrown away. Once we've reached the fixed point every block is rewritten one last
+//   time against its final fact, and those are the rewrites that end up in the graph. Only
+//   those last rewrites burn 'fuel'.
+pub fn analyze_and_rewrite_forward<L, A, F>(
     analysis: &mut A,
     graph: &Graph<L>,
     entry: Label,
-    fact_base: &mut FactBase<F>,
+    entry_fact: F,
+    mut fuel: Fuel,
+) -> Rewritten<L, F>
+where
+    L: Language,
+    A: ForwardAnalysis<L, F>,
+    F: Lattice,
+{
+    let mut facts = ForwardFacts::new(entry, entry_fact);
+
+    let region = graph.labels().collect();
+    let depths = FnvHashMap::default();
+    fixed_point_forward_graph(analysis, graph, &region, &depths, &[entry], &mut facts);
+    let (blocks, spliced_facts) = commit_forward_graph(
+        analysis,
+        graph,
+        &region,
+        &depths,
+        &[entry],
+        &facts,
+        &mut fuel,
+    );
+    let mut fact_base = facts.joined;
+    fact_base.extend(spliced_facts);
+
+    Rewritten {
+        graph: Graph::from_blocks(blocks),
+        facts: fact_base,
+        fuel_used: fuel.used(),
+    }
+}
+
+// The facts flowing into each block, both joined together and as they came in along each edge.
+struct ForwardFacts<F> {
+    joined: FactBase<F>,
+    incoming: FnvHashMap<Label, FactBase<F>>,
+}
+
+impl<F: Lattice> ForwardFacts<F> {
+    fn new(entry: Label, entry_fact: F) -> Self {
+        let mut joined = FnvHashMap::default();
+        joined.insert(entry, entry_fact);
+        ForwardFacts {
+            joined,
+            incoming: FnvHashMap::default(),
+        }
+    }
+
+    // Returns whether anything flowing into 'successor' changed.
+    fn join(&mut self, predecessor: Label, successor: Label, fact: F) -> bool {
+        let edge_changed = self
+            .incoming
+            .entry(successor)
+            .or_default()
+            .entry(predecessor)
+            .or_insert_with(F::bottom)
+            .join(&fact, successor);
+        let joined_changed = self
+            .joined
+            .entry(successor)
+            .or_insert_with(F::bottom)
+            .join(&fact, successor);
+        edge_changed || joined_changed
+    }
+}
+
+fn fixed_point_forward_graph<L, A, F>(
+    analysis: &mut A,
+    graph: &Graph<L>,
+    region: &FnvHashSet<Label>,
+    depths: &FnvHashMap<Label, Depths>,
+    entries: &[Label],
+    facts: &mut ForwardFacts<F>,
 ) where
     L: Language,
     A: ForwardAnalysis<L, F>,
     F: Lattice,
 {
-    let mut to_visit = graph.post_order_traversal(entry);
+    let mut to_visit = graph.post_order_within(entries, region);
 
     while let Some(label) = to_visit.pop() {
-        if !graph.contains(label) {
+        if !region.contains(&label) {
             // We don't need to analyze any blocks outside of our sub graph.
             continue;
         }
 
-        let output_fact_base = fixed_point_forward_block(analysis, &graph, label, fact_base);
+        let rewrite = fixed_point_forward_block(analysis, graph, label, facts, depths, None);
 
-        for successor in graph[label].successors() {
-            let old_fact = fact_base.entry(successor).or_insert_with(F::bottom);
-
-            if !old_fact.join(&output_fact_base[&successor], successor) {
+        for (successor, fact) in rewrite.output {
+            if !facts.join(label, successor, fact) {
                 // We didn't change so we don't need to re-examine this successor
                 continue;
             }
@@ -158,46 +317,144 @@ fn fixed_point_forward_graph<L, A, F>(
     }
 }
 
+// Rewrites every block in the region one last time, now that 'facts' holds the final joined
+//   facts and the ones coming in along each edge, and returns the rewritten blocks along with
+//   the facts for any blocks that were spliced in.
+fn commit_forward_graph<L, A, F>(
+    analysis: &mut A,
+    graph: &Graph<L>,
+    region: &FnvHashSet<Label>,
+    depths: &FnvHashMap<Label, Depths>,
+    entries: &[Label],
+    facts: &ForwardFacts<F>,
+    fuel: &mut Fuel,
+) -> (Vec<BasicBlock<L>>, FactBase<F>)
+where
+    L: Language,
+    A: ForwardAnalysis<L, F>,
+    F: Lattice,
+{
+    let mut blocks = vec![];
+    let mut spliced_facts = FnvHashMap::default();
+    let mut committed = FnvHashSet::default();
+
+    let mut to_visit = graph.post_order_within(entries, region);
+    while let Some(label) = to_visit.pop() {
+        if facts.joined.contains_key(&label) {
+            let rewrite =
+                fixed_point_forward_block(analysis, graph, label, facts, depths, Some(fuel));
+            blocks.extend(rewrite.blocks);
+            spliced_facts.extend(rewrite.facts);
+            committed.insert(label);
+        }
+    }
+
+    // Blocks we never reached are left exactly as they were.
+    for label in region {
+        if !committed.contains(label) {
+            blocks.push(graph[*label].clone());
+        }
+    }
+
+    (blocks, spliced_facts)
+}
+
 fn fixed_point_forward_block<L, A, F>(
     analysis: &mut A,
     graph: &Graph<L>,
     label: Label,
-    fact_base: &FactBase<F>,
-) -> FactBase<F>
+    facts: &ForwardFacts<F>,
+    depths: &FnvHashMap<Label, Depths>,
+    mut fuel: Option<&mut Fuel>,
+) -> BlockRewrite<L, F>
 where
     L: Language,
     A: ForwardAnalysis<L, F>,
     F: Lattice,
 {
-    let mut fact = fact_base
+    let mut block = graph[label].clone();
+    let mut depths = Depths::of(depths, &block);
+    let rewrite_depth = analysis.rewrite_depth();
+
+    // Blocks spliced in by sub-graph rewrites, which get analyzed once this block is done,
+    //   along with how deep their code is.
+    let mut spliced = vec![];
+    let mut spliced_depths = FnvHashMap::default();
+
+    let fact = facts
+        .joined
         .get(&label)
         .expect("We should always have a fact to start from")
         .clone();
-    let mut block = graph[label].clone();
-
-    fact = analysis.analyze_entry(graph, label, &block.entry, fact);
+    let no_edges = FnvHashMap::default();
+    let incoming = facts.incoming.get(&label).unwrap_or(&no_edges);
+    let mut fact = analysis.analyze_entry_edges(graph, label, &block.entry, incoming, fact);
 
     let mut index = 0;
     loop {
         while index < block.code.len() {
-            match analysis.analyze_instruction(
+            let depth = depths.code[index];
+            let rewrite = can_rewrite(&fuel) && rewrite_depth.allows(depth);
+            let before = (!rewrite).then(|| fact.clone());
+            let result = analysis.analyze_instruction(
                 graph,
                 label,
                 &block.code[index],
-                AnalyzeInstruction::new(&mut fact),
-            ) {
+                AnalyzeInstruction::new(
+                    &mut fact,
+                    Budget::new(rewrite, depth, fuel.as_deref_mut()),
+                ),
+            );
+            if result.is_some() {
+                if let Some(before) = before {
+                    // The instruction stays as it is, so whatever was done to the fact for the
+                    //   rewrite can't be kept. It gets analyzed again from where it started.
+                    fact = before;
+                    let result = analysis.analyze_instruction(
+                        graph,
+                        label,
+                        &block.code[index],
+                        AnalyzeInstruction::new(&mut fact, Budget::none()),
+                    );
+                    assert!(
+                        result.is_none(),
+                        "Instructions can only be rewritten when can_rewrite() allows it"
+                    );
+                    index += 1;
+                    continue;
+                }
+                burn(&mut fuel);
+            }
+
+            match result {
                 Some(RewriteInstruction(RewriteInstructionEnum::Single(inst))) => {
                     block.code[index] = inst;
+                    depths.replace(index, 1);
                 }
                 Some(RewriteInstruction(RewriteInstructionEnum::Multiple(insts))) => {
+                    depths.replace(index, insts.len());
                     block.code.splice(index..index + 1, insts);
                 }
-                Some(RewriteInstruction(RewriteInstructionEnum::Graph(
-                    _exit,
-                    _sub_graph,
-                    _entry,
-                ))) => {
-                    panic!("Unimplemented");
+                Some(RewriteInstruction(RewriteInstructionEnum::Graph(exit, sub_graph, entry))) => {
+                    // The block ends here, jumping into the sub-graph, and the rest of its code
+                    //   moves into a new block that the sub-graph can jump back into. That code
+                    //   keeps its depth, it's only the jump and the sub-graph that are new.
+                    let rest = block.code.split_off(index + 1);
+                    block.code.pop();
+                    let rest_exit = std::mem::replace(&mut block.exit, exit);
+                    let rest_depths = Depths {
+                        code: depths.code.split_off(index + 1),
+                        exit: std::mem::replace(&mut depths.exit, depth + 1),
+                    };
+                    depths.code.pop();
+
+                    let rest_block = BasicBlock::new(entry, rest, rest_exit);
+                    spliced_depths.insert(rest_block.label(), rest_depths);
+                    spliced.push(rest_block);
+                    for sub_block in sub_graph.blocks() {
+                        spliced_depths.insert(sub_block.label(), Depths::new(sub_block, depth + 1));
+                        spliced.push(sub_block.clone());
+                    }
                 }
                 None => {
                     index += 1;
@@ -205,20 +462,164 @@ where
             }
         }
 
-        match analysis.analyze_exit(graph, label, &block.exit, &fact) {
+        let depth = depths.exit;
+        let rewrite = can_rewrite(&fuel) && rewrite_depth.allows(depth);
+        let result = analysis.analyze_exit(
+            graph,
+            label,
+            &block.exit,
+            AnalyzeExit::new(&fact, Budget::new(rewrite, depth, fuel.as_deref_mut())),
+        );
+        let result = match result {
+            RewriteExit::Done(facts) => RewriteExit::Done(facts),
+            _ if !rewrite => {
+                // Like with instructions, the exit stays and gets analyzed again as it is.
+                let result = analysis.analyze_exit(
+                    graph,
+                    label,
+                    &block.exit,
+                    AnalyzeExit::new(&fact, Budget::none()),
+                );
+                assert!(
+                    matches!(result, RewriteExit::Done(_)),
+                    "Exits can only be rewritten when can_rewrite() allows it"
+                );
+                result
+            }
+            result => {
+                burn(&mut fuel);
+                result
+            }
+        };
+
+        match result {
             RewriteExit::Done(facts) => {
-                return facts;
+                let facts = facts
+                    .into_iter()
+                    .map(|(successor, fact)| {
+                        // The successor might be one of the blocks we've just spliced in.
+                        let entry = spliced
+                            .iter()
+                            .find(|spliced_block| spliced_block.label() == successor)
+                            .or_else(|| graph.get(successor))
+                            .map(|successor| &successor.entry);
+                        let fact = match entry {
+                            Some(entry) => {
+                                analysis.analyze_edge(graph, label, &block.exit, entry, fact)
+                            }
+                            None => fact,
+                        };
+                        (successor, fact)
+                    })
+                    .collect();
+                if spliced.is_empty() {
+                    return BlockRewrite {
+                        blocks: vec![block],
+                        facts: FnvHashMap::default(),
+                        output: facts,
+                    };
+                }
+                return fixed_point_forward_splice(
+                    analysis,
+                    graph,
+                    block,
+                    spliced,
+                    &spliced_depths,
+                    facts,
+                    fuel,
+                );
             }
             RewriteExit::Single(exit) => {
                 block.exit = exit;
+                depths.exit = depth + 1;
             }
             RewriteExit::Extend(insts, exit) => {
-                block.code.extend(insts.into_iter());
+                depths
+                    .code
+                    .extend(std::iter::repeat(depth + 1).take(insts.len()));
+                depths.exit = depth + 1;
+                block.code.extend(insts);
                 block.exit = exit;
             }
-            RewriteExit::Graph(_exit, _sub_graph) => {
-                panic!("Unimplemented");
+            RewriteExit::Graph(exit, sub_graph) => {
+                // The sub-graph sits between this block and wherever it used to go.
+                block.exit = exit;
+                depths.exit = depth + 1;
+                for sub_block in sub_graph.blocks() {
+                    spliced_depths.insert(sub_block.label(), Depths::new(sub_block, depth + 1));
+                    spliced.push(sub_block.clone());
+                }
             }
         }
     }
 }
+
+// Runs a fixed point over the blocks that were spliced in after 'block', starting from the
+//   facts its exit sends into them. Whatever leaves the spliced blocks is the output of the
+//   block as a whole.
+fn fixed_point_forward_splice<L, A, F>(
+    analysis: &mut A,
+    graph: &Graph<L>,
+    block: BasicBlock<L>,
+    spliced: Vec<BasicBlock<L>>,
+    depths: &FnvHashMap<Label, Depths>,
+    facts: FactBase<F>,
+    fuel: Option<&mut Fuel>,
+) -> BlockRewrite<L, F>
+where
+    L: Language,
+    A: ForwardAnalysis<L, F>,
+    F: Lattice,
+{
+    assert_new_labels(graph, &spliced);
+    let region: FnvHashSet<Label> = spliced.iter().map(BasicBlock::label).collect();
+    let entries = block.successors();
+
+    // The analysis sees the spliced blocks in a copy of the graph where they've taken the place
+    //   of the original block.
+    let mut sub_graph = graph.clone();
+    sub_graph.insert_block(block.clone());
+    for spliced_block in spliced {
+        sub_graph.insert_block(spliced_block);
+    }
+
+    // Everything the spliced blocks start from came in through the block's exit.
+    let mut fact_base = ForwardFacts {
+        joined: FnvHashMap::default(),
+        incoming: FnvHashMap::default(),
+    };
+    for (successor, fact) in facts {
+        fact_base.join(block.label(), successor, fact);
+    }
+    fixed_point_forward_graph(
+        analysis,
+        &sub_graph,
+        &region,
+        depths,
+        &entries,
+        &mut fact_base,
+    );
+
+    let mut rewrite = BlockRewrite {
+        blocks: vec![block],
+        facts: FnvHashMap::default(),
+        output: FnvHashMap::default(),
+    };
+    let committed = fuel.map(|fuel| {
+        commit_forward_graph(
+            analysis, &sub_graph, &region, depths, &entries, &fact_base, fuel,
+        )
+    });
+    for (label, fact) in fact_base.joined {
+        if region.contains(&label) {
+            rewrite.facts.insert(label, fact);
+        } else {
+            rewrite.output.insert(label, fact);
+        }
+    }
+    if let Some((blocks, facts)) = committed {
+        rewrite.blocks.extend(blocks);
+        rewrite.facts.extend(facts);
+    }
+    rewrite
+}
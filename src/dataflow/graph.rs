@@ -1,7 +1,10 @@
 use fnv::{FnvHashMap, FnvHashSet};
 
 use std::fmt;
-use std::ops::Index;
+use std::hash::Hash;
+use std::ops::{Index, IndexMut};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, OnceLock};
 
 // A label is an unsigned integer, used to identify a block.
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
@@ -13,6 +16,82 @@ impl fmt::Debug for Label {
     }
 }
 
+// Hands out labels that nothing else has, for the passes that make new blocks. A graph's
+//   supply starts after its highest label and is shared with its clones, so a rewrite can take
+//   labels from the graph it's looking at without running into anything that's already there.
+//
+// The last label, u32::MAX, is never handed out. A supply that gets to it has run out.
+#[derive(Clone, Debug)]
+pub struct LabelSupply {
+    next: Arc<AtomicU32>,
+}
+
+impl LabelSupply {
+    pub fn starting_at(label: Label) -> LabelSupply {
+        LabelSupply {
+            next: Arc::new(AtomicU32::new(label.0)),
+        }
+    }
+
+    pub fn fresh(&self) -> Label {
+        self.next
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+                next.checked_add(1)
+            })
+            .map(Label)
+            .expect("Every label has already been handed out")
+    }
+
+    // Makes sure the label is never handed out, since something is already using it.
+    fn reserve(&self, label: Label) {
+        self.next.fetch_max(after(label), Ordering::Relaxed);
+    }
+}
+
+// The first label a supply can hand out once this one is taken. With u32::MAX taken, that's
+//   u32::MAX, which means there aren't any left.
+fn after(label: Label) -> u32 {
+    label.0.saturating_add(1)
+}
+
+// What can be wrong with the blocks of a graph.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GraphError {
+    // More than one block has this label.
+    DuplicateLabel(Label),
+
+    // The block 'from' can jump to 'to', which isn't in the graph.
+    DanglingSuccessor { from: Label, to: Label },
+
+    // There's no block with the entry label.
+    MissingEntry(Label),
+
+    // The block stored under 'label' has an entry that says it's 'entry', which can happen if
+    //   it's changed after going into the graph.
+    EntryLabelMismatch { label: Label, entry: Label },
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GraphError::DuplicateLabel(label) => {
+                write!(f, "more than one block is labelled {:?}", label)
+            }
+            GraphError::DanglingSuccessor { from, to } => {
+                write!(f, "{:?} jumps to {:?}, which isn't in the graph", from, to)
+            }
+            GraphError::MissingEntry(label) => {
+                write!(f, "the entry {:?} isn't in the graph", label)
+            }
+            GraphError::EntryLabelMismatch { label, entry } => {
+                write!(f, "the block at {:?} is labelled {:?}", label, entry)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
 pub trait Entry: Clone {
     fn label(&self) -> Label;
 }
@@ -29,6 +108,69 @@ pub trait Language: Clone {
     type Exit: Exit;
 }
 
+// For the passes that make new blocks, and change where existing ones jump to.
+pub trait ControlFlow: Language {
+    fn label_entry(label: Label) -> Self::Entry;
+
+    // The same entry, for a copy of its block with a different label.
+    fn relabel_entry(entry: &Self::Entry, label: Label) -> Self::Entry;
+
+    fn jump(target: Label) -> Self::Exit;
+
+    // The same exit, with every label it can jump to passed through 'f'.
+    fn map_successors(exit: &Self::Exit, f: &dyn Fn(Label) -> Label) -> Self::Exit;
+
+    // Puts a new block, 'split', on the edge from 'exit' to 'successor'. Gives back 'exit' going
+    //   to 'split' instead, along with the exit of 'split' on to 'successor'. Exits that pass
+    //   arguments along their edges have to move them onto the exit of 'split', which can still
+    //   see everything the old exit could.
+    fn split_edge(exit: &Self::Exit, successor: Label, split: Label) -> (Self::Exit, Self::Exit) {
+        let exit = Self::map_successors(exit, &|label| {
+            if label == successor {
+                split
+            } else {
+                label
+            }
+        });
+        (exit, Self::jump(successor))
+    }
+}
+
+// Which variables the code of a language reads and writes, for the passes that work the same
+//   way for any language that can say so, like liveness.
+pub trait DefUse: Language {
+    type Var: Copy + Eq + Hash;
+
+    fn instruction_uses(instruction: &Self::Instruction) -> Vec<Self::Var>;
+
+    fn instruction_defs(instruction: &Self::Instruction) -> Vec<Self::Var>;
+
+    fn exit_uses(exit: &Self::Exit) -> Vec<Self::Var>;
+
+    // Most exits only jump somewhere, but a call might hand back a result, say.
+    fn exit_defs(_exit: &Self::Exit) -> Vec<Self::Var> {
+        vec![]
+    }
+}
+
+// For languages that pass values along edges, with blocks that take parameters and exits that
+//   pass arguments for them, instead of using phis.
+pub trait BlockParams: DefUse {
+    fn entry_params(entry: &Self::Entry) -> Vec<Self::Var>;
+
+    // The arguments 'exit' passes to the parameters of 'successor'.
+    fn exit_args(exit: &Self::Exit, successor: Label) -> Vec<Self::Var>;
+
+    // Each of the parameters of 'successor' paired with the argument 'exit' passes for it. All
+    //   of them are bound at once, as though by parallel copies.
+    fn edge_bindings(exit: &Self::Exit, successor: &Self::Entry) -> Vec<(Self::Var, Self::Var)> {
+        Self::entry_params(successor)
+            .into_iter()
+            .zip(Self::exit_args(exit, successor.label()))
+            .collect()
+    }
+}
+
 #[derive(Clone)]
 pub struct BasicBlock<L: Language> {
     pub entry: L::Entry,
@@ -53,54 +195,247 @@ impl<L: Language> BasicBlock<L> {
 #[derive(Clone)]
 pub struct Graph<L: Language> {
     blocks: FnvHashMap<Label, BasicBlock<L>>,
+
+    // Built the first time someone asks for predecessors, and thrown away whenever the blocks
+    //   change underneath it.
+    predecessors: OnceLock<FnvHashMap<Label, Vec<Label>>>,
+
+    labels: LabelSupply,
 }
 
 impl<L: Language> Graph<L> {
+    // If more than one block has the same label, the last one wins. Use try_from_blocks to
+    //   find out about that, and anything else wrong with the blocks.
     pub fn from_blocks(blocks: Vec<BasicBlock<L>>) -> Graph<L> {
-        // TODO: Error if multiple blocks w/ same label
         let mut map = FnvHashMap::default();
         for block in blocks {
             map.insert(block.label(), block);
         }
-        Graph { blocks: map }
+        let next = map.keys().map(|label| after(*label)).max().unwrap_or(0);
+        Graph {
+            blocks: map,
+            predecessors: OnceLock::new(),
+            labels: LabelSupply::starting_at(Label(next)),
+        }
     }
 
-    pub fn post_order_traversal(&self, entry: Label) -> Vec<Label> {
-        fn go<L: Language>(
-            graph: &Graph<L>,
-            output: &mut Vec<Label>,
-            visited: &mut FnvHashSet<Label>,
-            label: Label,
-        ) {
-            if !visited.insert(label) {
-                return;
+    pub fn try_from_blocks(
+        blocks: Vec<BasicBlock<L>>,
+        entry: Label,
+    ) -> Result<Graph<L>, GraphError> {
+        let mut seen = FnvHashSet::default();
+        for block in &blocks {
+            if !seen.insert(block.label()) {
+                return Err(GraphError::DuplicateLabel(block.label()));
             }
-            for successor in graph.blocks[&label].successors() {
-                go(graph, output, visited, successor);
+        }
+        let graph = Graph::from_blocks(blocks);
+        graph.validate(entry)?;
+        Ok(graph)
+    }
+
+    // Checks that the graph hangs together: every block is stored under its own label, the
+    //   entry is there, and every jump goes to a block that's in the graph. Blocks are checked
+    //   in label order, and the first problem found is the one reported.
+    pub fn validate(&self, entry: Label) -> Result<(), GraphError> {
+        let mut labels: Vec<Label> = self.labels().collect();
+        labels.sort_by_key(|label| label.0);
+
+        for label in &labels {
+            let block_label = self.blocks[label].label();
+            if block_label != *label {
+                return Err(GraphError::EntryLabelMismatch {
+                    label: *label,
+                    entry: block_label,
+                });
             }
-            output.push(label);
         }
+        if !self.contains(entry) {
+            return Err(GraphError::MissingEntry(entry));
+        }
+        for label in &labels {
+            for successor in self.blocks[label].successors() {
+                if !self.contains(successor) {
+                    return Err(GraphError::DanglingSuccessor {
+                        from: *label,
+                        to: successor,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn label_supply(&self) -> &LabelSupply {
+        &self.labels
+    }
 
+    // A label that isn't used by any block in the graph, and won't be handed out again.
+    pub fn fresh_label(&self) -> Label {
+        self.labels.fresh()
+    }
+
+    pub fn post_order_traversal(&self, entry: Label) -> Vec<Label> {
         let mut output = vec![];
         let mut visited = FnvHashSet::default();
-        go(self, &mut output, &mut visited, entry);
+        self.post_order_from(&mut output, &mut visited, &|_| true, entry);
         output
     }
 
+    // A post order traversal starting from each of the entries in turn, which never steps
+    //   outside of the labels in 'within'.
+    pub(super) fn post_order_within(
+        &self,
+        entries: &[Label],
+        within: &FnvHashSet<Label>,
+    ) -> Vec<Label> {
+        let mut output = vec![];
+        let mut visited = FnvHashSet::default();
+        for entry in entries {
+            self.post_order_from(
+                &mut output,
+                &mut visited,
+                &|label| within.contains(&label),
+                *entry,
+            );
+        }
+        output
+    }
+
+    fn post_order_from(
+        &self,
+        output: &mut Vec<Label>,
+        visited: &mut FnvHashSet<Label>,
+        within: &dyn Fn(Label) -> bool,
+        label: Label,
+    ) {
+        if !within(label) || !visited.insert(label) {
+            return;
+        }
+        // A jump to a block that isn't there goes nowhere, see validate.
+        let Some(block) = self.blocks.get(&label) else {
+            return;
+        };
+        for successor in block.successors() {
+            self.post_order_from(output, visited, within, successor);
+        }
+        output.push(label);
+    }
+
+    // The blocks, labels, or both, in no particular order.
+    pub fn blocks(&self) -> impl Iterator<Item = &BasicBlock<L>> {
+        self.blocks.values()
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = Label> + '_ {
+        self.blocks.keys().cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Label, &BasicBlock<L>)> {
+        self.blocks.iter().map(|(label, block)| (*label, block))
+    }
+
+    // Adds a block to the graph, handing back whatever block already had its label.
+    pub fn insert_block(&mut self, block: BasicBlock<L>) -> Option<BasicBlock<L>> {
+        self.predecessors.take();
+        self.labels.reserve(block.label());
+        self.blocks.insert(block.label(), block)
+    }
+
+    // Anything that jumped to the block is left jumping to a label that isn't in the graph.
+    pub fn remove_block(&mut self, label: Label) -> Option<BasicBlock<L>> {
+        self.predecessors.take();
+        self.blocks.remove(&label)
+    }
+
     pub fn contains(&self, label: Label) -> bool {
         self.blocks.contains_key(&label)
     }
 
-    pub fn direct_predecessors(&self, label: Label) -> Vec<Label> {
-        let mut output = vec![];
-        for (label, block) in &self.blocks {
-            for successor in block.successors() {
-                if successor == *label {
-                    output.push(*label);
+    pub fn get(&self, label: Label) -> Option<&BasicBlock<L>> {
+        self.blocks.get(&label)
+    }
+
+    // The block can be changed in any way other than its label, which has to stay the same.
+    pub fn get_mut(&mut self, label: Label) -> Option<&mut BasicBlock<L>> {
+        self.predecessors.take();
+        self.blocks.get_mut(&label)
+    }
+
+    // The labels of the blocks that jump to this label, each listed once, in label order.
+    pub fn predecessors(&self, label: Label) -> &[Label] {
+        let predecessors = self.predecessors.get_or_init(|| {
+            let mut map: FnvHashMap<Label, Vec<Label>> = FnvHashMap::default();
+            for (predecessor, block) in &self.blocks {
+                for successor in block.successors() {
+                    let entry = map.entry(successor).or_default();
+                    if !entry.contains(predecessor) {
+                        entry.push(*predecessor);
+                    }
                 }
             }
+            for entry in map.values_mut() {
+                entry.sort_by_key(|label| label.0);
+            }
+            map
+        });
+        predecessors.get(&label).map_or(&[], Vec::as_slice)
+    }
+
+    // Kept around for anything still using it, 'predecessors' doesn't need to allocate.
+    pub fn direct_predecessors(&self, label: Label) -> Vec<Label> {
+        self.predecessors(label).to_vec()
+    }
+}
+
+impl<L: ControlFlow> Graph<L> {
+    // Makes the block 'from' jump to 'target' wherever it used to jump to 'to'.
+    pub fn redirect(&mut self, from: Label, to: Label, target: Label) {
+        if let Some(block) = self.get_mut(from) {
+            block.exit = L::map_successors(&block.exit, &|successor| {
+                if successor == to {
+                    target
+                } else {
+                    successor
+                }
+            });
+        }
+    }
+
+    // Makes everything that jumps to 'to' jump to 'target' instead, which includes 'target'
+    //   itself if it's already in the graph and jumps there, so add it afterwards.
+    pub fn redirect_all(&mut self, to: Label, target: Label) {
+        for from in self.predecessors(to).to_vec() {
+            self.redirect(from, to, target);
         }
-        output
+    }
+
+    // A copy of the graph where every block has a new label from 'supply', along with where
+    //   each label went. Jumps between the blocks follow them to their new labels, and jumps
+    //   out of the graph are left alone, so a sub-graph can be made to fit into another graph
+    //   before it's spliced in. Phis still name the old labels of the blocks they come from.
+    pub fn rename_labels(&self, supply: &LabelSupply) -> (Graph<L>, FnvHashMap<Label, Label>) {
+        let mut labels: Vec<Label> = self.labels().collect();
+        labels.sort_by_key(|label| label.0);
+        let renamed: FnvHashMap<Label, Label> = labels
+            .iter()
+            .map(|label| (*label, supply.fresh()))
+            .collect();
+
+        let blocks = labels
+            .iter()
+            .map(|label| {
+                let block = &self[*label];
+                BasicBlock::new(
+                    L::relabel_entry(&block.entry, renamed[label]),
+                    block.code.clone(),
+                    L::map_successors(&block.exit, &|successor| {
+                        renamed.get(&successor).cloned().unwrap_or(successor)
+                    }),
+                )
+            })
+            .collect();
+        (Graph::from_blocks(blocks), renamed)
     }
 }
 
@@ -111,3 +446,12 @@ impl<L: Language> Index<Label> for Graph<L> {
         &self.blocks[&label]
     }
 }
+
+impl<L: Language> IndexMut<Label> for Graph<L> {
+    fn index_mut(&mut self, label: Label) -> &mut Self::Output {
+        self.predecessors.take();
+        self.blocks
+            .get_mut(&label)
+            .expect("No block with that label")
+    }
+}
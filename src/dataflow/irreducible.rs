@@ -0,0 +1,154 @@
+use fnv::{FnvHashMap, FnvHashSet};
+
+use super::graph::{BasicBlock, ControlFlow, Graph, Label, Language};
+use super::scc::{components_within, is_cycle};
+
+// A cycle that can be jumped into at more than one block, so none of them dominates the rest
+//   and it isn't a natural loop.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IrreducibleRegion {
+    // The blocks in the region that something outside of it jumps to, in label order.
+    pub entries: Vec<Label>,
+
+    // Every block in the region, in label order.
+    pub body: Vec<Label>,
+}
+
+// Finds the irreducible regions among the blocks reachable from the entry. Each strongly
+//   connected component with a single entry is a loop, and we keep looking inside it for
+//   cycles that don't go through its header. An irreducible region might have more of them
+//   inside of it, but only the outermost one is reported.
+pub fn irreducible_regions<L: Language>(graph: &Graph<L>, entry: Label) -> Vec<IrreducibleRegion> {
+    let reachable: FnvHashSet<Label> = graph.post_order_traversal(entry).into_iter().collect();
+    let mut regions = vec![];
+    find_regions(graph, entry, &reachable, reachable.clone(), &mut regions);
+    regions.sort_by_key(|region| region.body[0].0);
+    regions
+}
+
+pub fn is_reducible<L: Language>(graph: &Graph<L>, entry: Label) -> bool {
+    irreducible_regions(graph, entry).is_empty()
+}
+
+fn find_regions<L: Language>(
+    graph: &Graph<L>,
+    entry: Label,
+    reachable: &FnvHashSet<Label>,
+    within: FnvHashSet<Label>,
+    regions: &mut Vec<IrreducibleRegion>,
+) {
+    for component in components_within(graph, &within) {
+        if !is_cycle(graph, &component) {
+            continue;
+        }
+
+        let body: FnvHashSet<Label> = component.iter().cloned().collect();
+        let entries: Vec<Label> = component
+            .iter()
+            .filter(|label| {
+                **label == entry
+                    || graph.predecessors(**label).iter().any(|predecessor| {
+                        reachable.contains(predecessor) && !body.contains(predecessor)
+                    })
+            })
+            .cloned()
+            .collect();
+
+        match entries[..] {
+            // Without the header, whatever cycles are left are the loops inside this one.
+            [header] => {
+                let mut inside = body;
+                inside.remove(&header);
+                find_regions(graph, entry, reachable, inside, regions);
+            }
+            _ => regions.push(IrreducibleRegion {
+                entries,
+                body: component,
+            }),
+        }
+    }
+}
+
+// Makes the blocks reachable from the entry reducible by node splitting. For each irreducible
+//   region we pick one of its entries to be the header, and everything that jumped to any of
+//   the other entries jumps into a copy of the blocks those entries can get to without going
+//   through the header instead. The copies can only get back into the region through the
+//   header, so the original is now a loop, and the copies are left with a smaller region than
+//   before if they have one at all. We go until there aren't any left.
+//
+// This can make the graph a lot bigger in the worst case. Copied blocks get their labels from
+//   the graph's label supply, and since phis name the blocks they come from, it's meant to be
+//   run before going into SSA.
+pub fn split_nodes<L: ControlFlow>(graph: &Graph<L>, entry: Label) -> Graph<L> {
+    let mut graph = graph.clone();
+    while let Some(region) = irreducible_regions(&graph, entry).into_iter().next() {
+        graph = split_region(&graph, entry, &region);
+    }
+    graph
+}
+
+fn split_region<L: ControlFlow>(
+    graph: &Graph<L>,
+    entry: Label,
+    region: &IrreducibleRegion,
+) -> Graph<L> {
+    // The entry of the graph can't be moved, so it has to be the header if it's in here.
+    let header = if region.entries.contains(&entry) {
+        entry
+    } else {
+        region.entries[0]
+    };
+    let others: Vec<Label> = region
+        .entries
+        .iter()
+        .filter(|label| **label != header)
+        .cloned()
+        .collect();
+    let body: FnvHashSet<Label> = region.body.iter().cloned().collect();
+
+    let mut copied = FnvHashSet::default();
+    let mut to_visit = others.clone();
+    while let Some(label) = to_visit.pop() {
+        if label == header || !body.contains(&label) || !copied.insert(label) {
+            continue;
+        }
+        to_visit.extend(graph[label].successors());
+    }
+    let mut copied: Vec<Label> = copied.into_iter().collect();
+    copied.sort_by_key(|label| label.0);
+
+    let copies: FnvHashMap<Label, Label> = copied
+        .iter()
+        .map(|label| (*label, graph.fresh_label()))
+        .collect();
+
+    let mut blocks: Vec<BasicBlock<L>> = graph
+        .blocks()
+        .map(|block| {
+            if body.contains(&block.label()) {
+                return block.clone();
+            }
+            let exit = L::map_successors(&block.exit, &|successor| {
+                if others.contains(&successor) {
+                    copies[&successor]
+                } else {
+                    successor
+                }
+            });
+            BasicBlock::new(block.entry.clone(), block.code.clone(), exit)
+        })
+        .collect();
+    for label in &copied {
+        let block = &graph[*label];
+        let exit = L::map_successors(&block.exit, &|successor| {
+            copies.get(&successor).cloned().unwrap_or(successor)
+        });
+        blocks.push(BasicBlock::new(
+            L::relabel_entry(&block.entry, copies[label]),
+            block.code.clone(),
+            exit,
+        ));
+    }
+
+    Graph::from_blocks(blocks)
+}
@@ -12,3 +12,17 @@ pub trait Lattice: Sized + Clone {
     //   If you were to reach your top-most fact, this would always return false.
     fn join(&mut self, other: &Self, label: Label) -> bool;
 }
+
+// A pair of facts, so that two analyses can run side by side in the same fixed point. The
+//   pair only stops changing once both halves have.
+impl<A: Lattice, B: Lattice> Lattice for (A, B) {
+    fn bottom() -> Self {
+        (A::bottom(), B::bottom())
+    }
+
+    fn join(&mut self, other: &Self, label: Label) -> bool {
+        let first = self.0.join(&other.0, label);
+        let second = self.1.join(&other.1, label);
+        first || second
+    }
+}
@@ -0,0 +1,77 @@
+use fnv::{FnvHashMap, FnvHashSet};
+
+use std::hash::Hash;
+
+use super::backward_analysis::*;
+use super::fact_base::FactBase;
+use super::graph::{DefUse, Graph, Label};
+use super::lattice::Lattice;
+
+// The variables that are live on the way out of a block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LiveVars<V: Eq + Hash> {
+    pub vars: FnvHashSet<V>,
+}
+
+impl<V: Copy + Eq + Hash> Lattice for LiveVars<V> {
+    fn bottom() -> Self {
+        LiveVars {
+            vars: FnvHashSet::default(),
+        }
+    }
+
+    fn join(&mut self, other: &Self, _label: Label) -> bool {
+        let before = self.vars.len();
+        self.vars.extend(other.vars.iter().cloned());
+        before != self.vars.len()
+    }
+}
+
+// Liveness for any language that knows its defs and uses. It never rewrites anything.
+pub struct Liveness;
+
+impl<L: DefUse> BackwardAnalysis<L, LiveVars<L::Var>> for Liveness {
+    fn analyze_exit(
+        &mut self,
+        _graph: &Graph<L>,
+        _label: Label,
+        exit: &L::Exit,
+        analyze: AnalyzeExitBackward<LiveVars<L::Var>>,
+    ) -> Option<RewriteExitBackward<L>> {
+        let fact = analyze.fact_mut();
+        for var in L::exit_defs(exit) {
+            fact.vars.remove(&var);
+        }
+        fact.vars.extend(L::exit_uses(exit));
+        None
+    }
+
+    fn analyze_instruction(
+        &mut self,
+        _graph: &Graph<L>,
+        _label: Label,
+        instruction: &L::Instruction,
+        analyze: AnalyzeInstructionBackward<LiveVars<L::Var>>,
+    ) -> Option<RewriteInstructionBackward<L>> {
+        let fact = analyze.fact_mut();
+        for var in L::instruction_defs(instruction) {
+            fact.vars.remove(&var);
+        }
+        fact.vars.extend(L::instruction_uses(instruction));
+        None
+    }
+
+    fn analyze_entry(
+        &mut self,
+        graph: &Graph<L>,
+        label: Label,
+        _entry: &L::Entry,
+        fact: LiveVars<L::Var>,
+    ) -> FactBase<LiveVars<L::Var>> {
+        let mut facts = FnvHashMap::default();
+        for &predecessor in graph.predecessors(label) {
+            facts.insert(predecessor, fact.clone());
+        }
+        facts
+    }
+}
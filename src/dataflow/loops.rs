@@ -0,0 +1,196 @@
+use fnv::{FnvHashMap, FnvHashSet};
+
+use super::dominator::DominatorTree;
+use super::graph::{Graph, Label, Language};
+
+#[derive(Clone, Debug)]
+struct Loop {
+    latches: Vec<Label>,
+    body: Vec<Label>,
+    exits: Vec<Label>,
+    parent: Option<Label>,
+    children: Vec<Label>,
+    depth: usize,
+}
+
+// The natural loops of the blocks in a dominator tree, and how they nest. A loop is named by
+//   its header, and all the back edges into the same header make up one loop.
+//
+// Only edges jumping back to a block that dominates where they come from count as back
+//   edges, so cycles that can be entered in more than one place (irreducible ones) aren't
+//   loops here.
+#[derive(Clone, Debug)]
+pub struct LoopInfo {
+    loops: FnvHashMap<Label, Loop>,
+    headers: Vec<Label>,
+    roots: Vec<Label>,
+    innermost: FnvHashMap<Label, Label>,
+}
+
+impl LoopInfo {
+    pub fn new<L: Language>(graph: &Graph<L>, dominators: &DominatorTree) -> LoopInfo {
+        let mut latches: FnvHashMap<Label, Vec<Label>> = FnvHashMap::default();
+        for label in graph.post_order_traversal(dominators.entry()) {
+            for successor in graph[label].successors() {
+                if dominators.dominates(successor, label) {
+                    let header_latches = latches.entry(successor).or_default();
+                    if !header_latches.contains(&label) {
+                        header_latches.push(label);
+                    }
+                }
+            }
+        }
+
+        let mut loops = FnvHashMap::default();
+        for (header, mut latches) in latches {
+            latches.sort_by_key(|label| label.0);
+
+            // Everything that can get to a latch without going through the header.
+            let mut body = FnvHashSet::default();
+            body.insert(header);
+            let mut to_visit = latches.clone();
+            while let Some(label) = to_visit.pop() {
+                if body.insert(label) {
+                    to_visit.extend(
+                        graph
+                            .predecessors(label)
+                            .iter()
+                            .filter(|predecessor| dominators.contains(**predecessor)),
+                    );
+                }
+            }
+
+            let mut exits: Vec<Label> = body
+                .iter()
+                .flat_map(|label| graph[*label].successors())
+                .filter(|successor| !body.contains(successor))
+                .collect::<FnvHashSet<Label>>()
+                .into_iter()
+                .collect();
+            exits.sort_by_key(|label| label.0);
+            let mut body: Vec<Label> = body.into_iter().collect();
+            body.sort_by_key(|label| label.0);
+
+            loops.insert(
+                header,
+                Loop {
+                    latches,
+                    body,
+                    exits,
+                    parent: None,
+                    children: vec![],
+                    depth: 1,
+                },
+            );
+        }
+
+        // Two loops are either nested or don't share any blocks at all, so going from the
+        //   biggest down, the last loop to claim a block is the innermost one it's in.
+        let mut headers: Vec<Label> = loops.keys().cloned().collect();
+        headers.sort_by_key(|header| (std::cmp::Reverse(loops[header].body.len()), header.0));
+        let mut innermost: FnvHashMap<Label, Label> = FnvHashMap::default();
+        let mut roots = vec![];
+        for header in &headers {
+            let parent = innermost.get(header).cloned();
+            match parent {
+                Some(parent) => {
+                    let depth = loops[&parent].depth + 1;
+                    loops
+                        .get_mut(&parent)
+                        .expect("Blocks are only claimed by loops we've found")
+                        .children
+                        .push(*header);
+                    let current = loops.get_mut(header).expect("Every header has a loop");
+                    current.parent = Some(parent);
+                    current.depth = depth;
+                }
+                None => roots.push(*header),
+            }
+            for label in &loops[header].body {
+                innermost.insert(*label, *header);
+            }
+        }
+
+        for current in loops.values_mut() {
+            current.children.sort_by_key(|label| label.0);
+        }
+        headers.sort_by_key(|label| label.0);
+        roots.sort_by_key(|label| label.0);
+
+        LoopInfo {
+            loops,
+            headers,
+            roots,
+            innermost,
+        }
+    }
+
+    // The header of every loop, in label order.
+    pub fn headers(&self) -> &[Label] {
+        &self.headers
+    }
+
+    pub fn is_header(&self, label: Label) -> bool {
+        self.loops.contains_key(&label)
+    }
+
+    // The blocks that jump back to the header, in label order.
+    pub fn latches(&self, header: Label) -> &[Label] {
+        self.loops
+            .get(&header)
+            .map_or(&[], |current| &current.latches)
+    }
+
+    // Every block in the loop, including the header and the blocks of any loops inside it, in
+    //   label order.
+    pub fn body(&self, header: Label) -> &[Label] {
+        self.loops.get(&header).map_or(&[], |current| &current.body)
+    }
+
+    // The blocks outside of the loop that it can jump to, in label order.
+    pub fn exits(&self, header: Label) -> &[Label] {
+        self.loops
+            .get(&header)
+            .map_or(&[], |current| &current.exits)
+    }
+
+    // Whether the block is in the loop, or in a loop inside it.
+    pub fn contains(&self, header: Label, label: Label) -> bool {
+        let mut current = self.innermost(label);
+        while let Some(inner) = current {
+            if inner == header {
+                return true;
+            }
+            current = self.parent(inner);
+        }
+        false
+    }
+
+    // The loop this one is directly inside of.
+    pub fn parent(&self, header: Label) -> Option<Label> {
+        self.loops.get(&header).and_then(|current| current.parent)
+    }
+
+    // The loops directly inside this one, in label order.
+    pub fn children(&self, header: Label) -> &[Label] {
+        self.loops
+            .get(&header)
+            .map_or(&[], |current| &current.children)
+    }
+
+    // The loops that aren't inside any other loop, in label order.
+    pub fn roots(&self) -> &[Label] {
+        &self.roots
+    }
+
+    // The header of the innermost loop the block is in.
+    pub fn innermost(&self, label: Label) -> Option<Label> {
+        self.innermost.get(&label).cloned()
+    }
+
+    // How many loops the block is in, so 0 if it isn't in one at all.
+    pub fn depth(&self, label: Label) -> usize {
+        self.innermost(label)
+            .map_or(0, |header| self.loops[&header].depth)
+    }
+}
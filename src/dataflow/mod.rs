@@ -1,17 +1,33 @@
 mod backward_analysis;
+mod combinators;
+pub mod control_dependence;
+pub mod critical_edges;
 pub mod dominator;
 mod fact_base;
 mod forward_analysis;
 mod graph;
+pub mod irreducible;
 mod lattice;
+pub mod liveness;
+pub mod loops;
+mod rewrite;
+pub mod scc;
+pub mod ssa;
 
 pub use backward_analysis::{
-    backward_analysis, AnalyzeInstructionBackward, BackwardAnalysis, RewriteExitBackward,
+    analyze_and_rewrite_backward, backward_analysis, backward_analysis_with_boundary,
+    AnalyzeExitBackward, AnalyzeInstructionBackward, BackwardAnalysis, RewriteExitBackward,
     RewriteInstructionBackward,
 };
+pub use combinators::{OrElse, Pair, Then};
 pub use fact_base::FactBase;
 pub use forward_analysis::{
-    forward_analysis, AnalyzeInstruction, ForwardAnalysis, RewriteExit, RewriteInstruction,
+    analyze_and_rewrite_forward, forward_analysis, AnalyzeExit, AnalyzeInstruction,
+    ForwardAnalysis, RewriteExit, RewriteInstruction,
+};
+pub use graph::{
+    BasicBlock, BlockParams, ControlFlow, DefUse, Entry, Exit, Graph, GraphError, Instruction,
+    Label, LabelSupply, Language,
 };
-pub use graph::{BasicBlock, Entry, Exit, Graph, Instruction, Label, Language};
 pub use lattice::Lattice;
+pub use rewrite::{Fuel, RewriteDepth, Rewritten};
@@ -0,0 +1,215 @@
+use fnv::FnvHashMap;
+
+use super::fact_base::FactBase;
+use super::graph::{BasicBlock, Graph, Label, Language};
+
+// The result of analyzing and rewriting a graph: the graph with every rewrite the analysis
+//   made applied to it, and the facts flowing into each of its blocks.
+pub struct Rewritten<L: Language, F> {
+    pub graph: Graph<L>,
+    pub facts: FactBase<F>,
+
+    // How many rewrites made it into the graph.
+    pub fuel_used: usize,
+}
+
+// Optimization fuel, which bounds how many rewrites get committed to the graph. Each one burns
+//   a single unit, and once it's all gone the remaining blocks are analyzed without rewriting.
+//   Bisecting over the amount of fuel finds the exact rewrite that broke a program.
+#[derive(Copy, Clone, Debug)]
+pub struct Fuel {
+    remaining: Option<usize>,
+    used: usize,
+}
+
+impl Fuel {
+    pub fn unlimited() -> Fuel {
+        Fuel {
+            remaining: None,
+            used: 0,
+        }
+    }
+
+    pub fn limited(amount: usize) -> Fuel {
+        Fuel {
+            remaining: Some(amount),
+            used: 0,
+        }
+    }
+
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub(super) fn can_burn(&self, amount: usize) -> bool {
+        self.remaining.map_or(true, |remaining| remaining >= amount)
+    }
+
+    pub(super) fn burn(&mut self) {
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= 1;
+        }
+        self.used += 1;
+    }
+}
+
+// How far an analysis is allowed to go in rewriting its own rewrites.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RewriteDepth {
+    // Whatever a rewrite produces is analyzed, but never rewritten again.
+    Shallow,
+
+    // Whatever a rewrite produces can be rewritten again, and so on, up to this many rewrites
+    //   on top of the original code. Deep(0) is the same thing as Shallow.
+    Deep(usize),
+}
+
+impl RewriteDepth {
+    // Whichever of the two stops rewriting sooner.
+    pub(super) fn shallower(self, other: RewriteDepth) -> RewriteDepth {
+        match (self, other) {
+            (RewriteDepth::Deep(a), RewriteDepth::Deep(b)) => RewriteDepth::Deep(a.min(b)),
+            _ => RewriteDepth::Shallow,
+        }
+    }
+
+    pub(super) fn allows(self, depth: usize) -> bool {
+        match self {
+            RewriteDepth::Shallow => depth == 0,
+            RewriteDepth::Deep(limit) => depth <= limit,
+        }
+    }
+}
+
+// How many rewrites deep each instruction in a block is, where the code of the original graph
+//   is at depth 0. Blocks without an entry in a region's depths are entirely at depth 0.
+#[derive(Clone)]
+pub(super) struct Depths {
+    pub(super) code: Vec<usize>,
+    pub(super) exit: usize,
+}
+
+impl Depths {
+    pub(super) fn new<L: Language>(block: &BasicBlock<L>, depth: usize) -> Depths {
+        Depths {
+            code: vec![depth; block.code.len()],
+            exit: depth,
+        }
+    }
+
+    pub(super) fn of<L: Language>(
+        depths: &FnvHashMap<Label, Depths>,
+        block: &BasicBlock<L>,
+    ) -> Depths {
+        depths
+            .get(&block.label())
+            .cloned()
+            .unwrap_or_else(|| Depths::new(block, 0))
+    }
+
+    // Replaces the depth at 'index' with 'count' instructions one rewrite deeper.
+    pub(super) fn replace(&mut self, index: usize, count: usize) {
+        let depth = self.code[index] + 1;
+        self.code
+            .splice(index..index + 1, std::iter::repeat(depth).take(count));
+    }
+}
+
+// What analyzing a single block turned it into. That's just the one block when it was only
+//   rewritten in place, but a sub-graph rewrite splits it up into several, and we keep the facts
+//   for those new blocks apart from the facts flowing out to the rest of the graph.
+pub(super) struct BlockRewrite<L: Language, F> {
+    pub(super) blocks: Vec<BasicBlock<L>>,
+    pub(super) facts: FactBase<F>,
+    pub(super) output: FactBase<F>,
+}
+
+// Blocks spliced in by a sub-graph rewrite would quietly take the place of any block in the
+//   graph with the same label, so they have to come with new ones.
+pub(super) fn assert_new_labels<'a, L: Language + 'a>(
+    graph: &Graph<L>,
+    spliced: impl IntoIterator<Item = &'a BasicBlock<L>>,
+) {
+    for block in spliced {
+        assert!(
+            !graph.contains(block.label()),
+            "A sub-graph rewrite reused {:?}, which is already in the graph",
+            block.label()
+        );
+    }
+}
+
+// While we're still iterating towards the fixed point there's no fuel to worry about, and a
+//   block is only ever handed fuel when its rewrites are being committed.
+pub(super) fn can_rewrite(fuel: &Option<&mut Fuel>) -> bool {
+    fuel.as_ref().map_or(true, |fuel| fuel.can_burn(1))
+}
+
+pub(super) fn burn(fuel: &mut Option<&mut Fuel>) {
+    if let Some(fuel) = fuel {
+        fuel.burn();
+    }
+}
+
+// What the engine allows an analysis to rewrite at some point in a block. The combinators hand
+//   it down to the analyses they're made of, and the ones that rewrite what was just rewritten
+//   go a level deeper and burn the fuel for those extra rewrites themselves.
+pub(super) struct Budget<'a> {
+    rewrite: bool,
+    depth: usize,
+
+    // Rewrites that have been made but not burned yet, since whoever made them hasn't returned.
+    pending: usize,
+    fuel: Option<&'a mut Fuel>,
+}
+
+impl<'a> Budget<'a> {
+    pub(super) fn new(rewrite: bool, depth: usize, fuel: Option<&'a mut Fuel>) -> Budget<'a> {
+        Budget {
+            rewrite,
+            depth,
+            pending: 0,
+            fuel,
+        }
+    }
+
+    // For analyzing code without rewriting it.
+    pub(super) fn none() -> Budget<'a> {
+        Budget::new(false, 0, None)
+    }
+
+    // Anything still pending has to be burned as well, and fuel can run out part of the way
+    //   through rewriting a list of instructions.
+    pub(super) fn can_rewrite(&self) -> bool {
+        self.rewrite
+            && self
+                .fuel
+                .as_ref()
+                .map_or(true, |fuel| fuel.can_burn(self.pending + 1))
+    }
+
+    pub(super) fn reborrow(&mut self) -> Budget<'_> {
+        Budget {
+            rewrite: self.rewrite,
+            depth: self.depth,
+            pending: self.pending,
+            fuel: self.fuel.as_deref_mut(),
+        }
+    }
+
+    // For rewriting the code a rewrite allowed by this budget just came up with. That rewrite
+    //   hasn't been burned yet, so there has to be fuel left over for it.
+    pub(super) fn nested(&mut self, rewrite_depth: RewriteDepth) -> Budget<'_> {
+        let depth = self.depth + 1;
+        Budget {
+            rewrite: self.rewrite && rewrite_depth.allows(depth),
+            depth,
+            pending: self.pending + 1,
+            fuel: self.fuel.as_deref_mut(),
+        }
+    }
+
+    pub(super) fn burn(&mut self) {
+        burn(&mut self.fuel);
+    }
+}
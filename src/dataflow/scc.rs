@@ -0,0 +1,188 @@
+use fnv::{FnvHashMap, FnvHashSet};
+
+use super::graph::{Graph, Label, Language};
+
+// The strongly connected components of the graph, in topological order, so a component
+//   comes before every other component it can jump to. Each one is in label order.
+pub fn strongly_connected_components<L: Language>(graph: &Graph<L>) -> Vec<Vec<Label>> {
+    let within = graph.labels().collect();
+    let mut components = components_within(graph, &within);
+    components.reverse();
+    components
+}
+
+// The cycles that can't be reached from the entry, each in label order. These are the dead
+//   blocks that won't go away just by dropping whatever has no predecessors.
+pub fn unreachable_cycles<L: Language>(graph: &Graph<L>, entry: Label) -> Vec<Vec<Label>> {
+    let reachable: FnvHashSet<Label> = graph.post_order_traversal(entry).into_iter().collect();
+    let mut cycles: Vec<Vec<Label>> = strongly_connected_components(graph)
+        .into_iter()
+        .filter(|component| !reachable.contains(&component[0]) && is_cycle(graph, component))
+        .collect();
+    cycles.sort_by_key(|component| component[0].0);
+    cycles
+}
+
+// The graph with each strongly connected component squashed down into a single node, which
+//   leaves a DAG. Components are numbered in topological order, so working through them by
+//   number means everything that can jump into a component is done before it.
+#[derive(Clone, Debug)]
+pub struct Condensation {
+    components: Vec<Vec<Label>>,
+    component_of: FnvHashMap<Label, usize>,
+    cycles: Vec<bool>,
+    successors: Vec<Vec<usize>>,
+    predecessors: Vec<Vec<usize>>,
+}
+
+impl Condensation {
+    pub fn new<L: Language>(graph: &Graph<L>) -> Condensation {
+        let components = strongly_connected_components(graph);
+        let component_of: FnvHashMap<Label, usize> = components
+            .iter()
+            .enumerate()
+            .flat_map(|(index, component)| component.iter().map(move |label| (*label, index)))
+            .collect();
+
+        let mut successors = vec![vec![]; components.len()];
+        let mut predecessors = vec![vec![]; components.len()];
+        for (index, component) in components.iter().enumerate() {
+            let mut next: Vec<usize> = component
+                .iter()
+                .flat_map(|label| graph[*label].successors())
+                .filter_map(|successor| component_of.get(&successor).cloned())
+                .filter(|successor| *successor != index)
+                .collect();
+            next.sort();
+            next.dedup();
+            for successor in &next {
+                predecessors[*successor].push(index);
+            }
+            successors[index] = next;
+        }
+        let cycles = components
+            .iter()
+            .map(|component| is_cycle(graph, component))
+            .collect();
+
+        Condensation {
+            components,
+            component_of,
+            cycles,
+            successors,
+            predecessors,
+        }
+    }
+
+    // Every component, in topological order.
+    pub fn components(&self) -> &[Vec<Label>] {
+        &self.components
+    }
+
+    // The number of the component the block is in.
+    pub fn component(&self, label: Label) -> Option<usize> {
+        self.component_of.get(&label).cloned()
+    }
+
+    // The blocks in a component, in label order.
+    pub fn labels(&self, component: usize) -> &[Label] {
+        &self.components[component]
+    }
+
+    // Whether the blocks in the component can go around in a cycle.
+    pub fn is_cycle(&self, component: usize) -> bool {
+        self.cycles[component]
+    }
+
+    // The components this one can jump to directly, in order. These all come after it.
+    pub fn successors(&self, component: usize) -> &[usize] {
+        &self.successors[component]
+    }
+
+    // The components that can jump to this one directly, in order. These all come before it.
+    pub fn predecessors(&self, component: usize) -> &[usize] {
+        &self.predecessors[component]
+    }
+}
+
+// The strongly connected components of the blocks in 'within', only following edges that stay
+//   inside of it, found with Tarjan's algorithm. A component comes out after every component
+//   it can jump to, and each one is in label order.
+pub(super) fn components_within<L: Language>(
+    graph: &Graph<L>,
+    within: &FnvHashSet<Label>,
+) -> Vec<Vec<Label>> {
+    let mut labels: Vec<Label> = within.iter().cloned().collect();
+    labels.sort_by_key(|label| label.0);
+
+    let mut tarjan = Tarjan {
+        within,
+        index: FnvHashMap::default(),
+        low_link: FnvHashMap::default(),
+        stack: vec![],
+        on_stack: FnvHashSet::default(),
+        components: vec![],
+    };
+    for label in labels {
+        if !tarjan.index.contains_key(&label) {
+            tarjan.visit(graph, label);
+        }
+    }
+    tarjan.components
+}
+
+// Whether the component has a cycle in it, which it does unless it's a single block that
+//   doesn't jump to itself.
+pub(super) fn is_cycle<L: Language>(graph: &Graph<L>, component: &[Label]) -> bool {
+    match component {
+        [label] => graph[*label].successors().contains(label),
+        _ => true,
+    }
+}
+
+struct Tarjan<'a> {
+    within: &'a FnvHashSet<Label>,
+    index: FnvHashMap<Label, usize>,
+    low_link: FnvHashMap<Label, usize>,
+    stack: Vec<Label>,
+    on_stack: FnvHashSet<Label>,
+    components: Vec<Vec<Label>>,
+}
+
+impl Tarjan<'_> {
+    fn visit<L: Language>(&mut self, graph: &Graph<L>, label: Label) {
+        let index = self.index.len();
+        self.index.insert(label, index);
+        self.low_link.insert(label, index);
+        self.stack.push(label);
+        self.on_stack.insert(label);
+
+        for successor in graph[label].successors() {
+            if !self.within.contains(&successor) {
+                continue;
+            }
+            if !self.index.contains_key(&successor) {
+                self.visit(graph, successor);
+                let low_link = self.low_link[&label].min(self.low_link[&successor]);
+                self.low_link.insert(label, low_link);
+            } else if self.on_stack.contains(&successor) {
+                let low_link = self.low_link[&label].min(self.index[&successor]);
+                self.low_link.insert(label, low_link);
+            }
+        }
+
+        // Nothing below us could get back above us, so we're the root of a component.
+        if self.low_link[&label] == index {
+            let mut component = vec![];
+            while let Some(member) = self.stack.pop() {
+                self.on_stack.remove(&member);
+                component.push(member);
+                if member == label {
+                    break;
+                }
+            }
+            component.sort_by_key(|label| label.0);
+            self.components.push(component);
+        }
+    }
+}
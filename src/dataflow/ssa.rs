@@ -0,0 +1,347 @@
+use fnv::{FnvHashMap, FnvHashSet};
+
+use super::dominator::DominatorTree;
+use super::graph::{BasicBlock, ControlFlow, DefUse, Graph, Label};
+
+// The arguments of a phi, each one paired with the predecessor it comes from.
+pub type PhiArgs<V> = Vec<(Label, V)>;
+
+// What a language needs on top of its defs and uses to be put into SSA form: a way to make
+//   phis, and a way to rename the variables in its code.
+pub trait Ssa: DefUse {
+    // A phi assigning 'dst' whichever of the 'args' belongs to the predecessor we came from.
+    fn phi(dst: Self::Var, args: PhiArgs<Self::Var>) -> Self::Instruction;
+
+    // The destination and arguments of an instruction, if it's a phi.
+    fn as_phi(instruction: &Self::Instruction) -> Option<(Self::Var, &PhiArgs<Self::Var>)>;
+
+    fn copy(dst: Self::Var, src: Self::Var) -> Self::Instruction;
+
+    // The same instruction, with every variable it reads passed through 'uses' and every
+    //   variable it writes passed through 'defs'.
+    fn rename_instruction(
+        instruction: &Self::Instruction,
+        uses: &dyn Fn(Self::Var) -> Self::Var,
+        defs: &dyn Fn(Self::Var) -> Self::Var,
+    ) -> Self::Instruction;
+
+    fn rename_exit(
+        exit: &Self::Exit,
+        uses: &dyn Fn(Self::Var) -> Self::Var,
+        defs: &dyn Fn(Self::Var) -> Self::Var,
+    ) -> Self::Exit;
+}
+
+// Puts the blocks reachable from 'entry' into SSA form, with phis at the start of the blocks
+//   in the iterated dominance frontier of wherever a variable is assigned, and every
+//   assignment renamed to a variable made by 'fresh' from the original one. Blocks that can't
+//   be reached are left as they were.
+//
+// This builds minimal SSA, so there can be phis for variables that are dead by then. A
+//   variable read before anything assigns it keeps its original name, and so does a phi
+//   argument from a predecessor where it was never assigned.
+pub fn to_ssa<L, F>(graph: &Graph<L>, entry: Label, mut fresh: F) -> Graph<L>
+where
+    L: Ssa,
+    F: FnMut(L::Var) -> L::Var,
+{
+    let tree = DominatorTree::new(graph, entry);
+    let mut order = graph.post_order_traversal(entry);
+    order.reverse();
+
+    // Every variable, in the order we first see it assigned, and where it's assigned.
+    let mut vars = vec![];
+    let mut def_sites: FnvHashMap<L::Var, Vec<Label>> = FnvHashMap::default();
+    for label in &order {
+        let block = &graph[*label];
+        let defs = block
+            .code
+            .iter()
+            .flat_map(L::instruction_defs)
+            .chain(L::exit_defs(&block.exit));
+        for var in defs {
+            let sites = def_sites.entry(var).or_insert_with(|| {
+                vars.push(var);
+                vec![]
+            });
+            if !sites.contains(label) {
+                sites.push(*label);
+            }
+        }
+    }
+
+    let mut phis: FnvHashMap<Label, Vec<Phi<L::Var>>> = FnvHashMap::default();
+    for var in vars {
+        for label in tree.iterated_dominance_frontier(&def_sites[&var]) {
+            phis.entry(label).or_default().push(Phi {
+                var,
+                dst: var,
+                args: vec![],
+            });
+        }
+    }
+
+    let mut renamer = Renamer {
+        graph,
+        tree: &tree,
+        fresh: &mut fresh,
+        stacks: FnvHashMap::default(),
+        phis,
+        blocks: FnvHashMap::default(),
+    };
+    renamer.rename(entry);
+
+    let Renamer {
+        mut phis,
+        mut blocks,
+        ..
+    } = renamer;
+    let mut output = vec![];
+    for label in graph.labels() {
+        let mut block = match blocks.remove(&label) {
+            Some(block) => block,
+            None => {
+                output.push(graph[label].clone());
+                continue;
+            }
+        };
+        let block_phis = phis.remove(&label).unwrap_or_default();
+        let code = block_phis.into_iter().map(|mut phi| {
+            phi.args.sort_by_key(|(label, _)| label.0);
+            L::phi(phi.dst, phi.args)
+        });
+        block.code.splice(0..0, code);
+        output.push(block);
+    }
+    Graph::from_blocks(output)
+}
+
+// Takes the blocks reachable from 'entry' back out of SSA form, replacing the phis at the start
+//   of each block with copies on the way in from each of its predecessors. The copies for an
+//   edge go at the end of the predecessor when that's the only place it jumps, at the start of
+//   the block when that's the only way in, and otherwise into a new block on the edge, so they
+//   only ever happen on the way to the block with the phis. 'fresh' makes the temporaries
+//   needed to break cycles, like two phis swapping.
+//
+// New blocks get their labels from the graph's label supply.
+pub fn from_ssa<L, F>(graph: &Graph<L>, entry: Label, mut fresh: F) -> Graph<L>
+where
+    L: Ssa + ControlFlow,
+    F: FnMut(L::Var) -> L::Var,
+{
+    let mut blocks: FnvHashMap<Label, BasicBlock<L>> = graph
+        .blocks()
+        .map(|block| (block.label(), block.clone()))
+        .collect();
+
+    let mut order = graph.post_order_traversal(entry);
+    order.reverse();
+    for label in order {
+        let phis: Vec<_> = graph[label].code.iter().filter_map(L::as_phi).collect();
+        if phis.is_empty() {
+            continue;
+        }
+        blocks
+            .get_mut(&label)
+            .expect("Every label has a block")
+            .code
+            .retain(|instruction| L::as_phi(instruction).is_none());
+
+        for &predecessor in graph.predecessors(label) {
+            let copies: Vec<(L::Var, L::Var)> = phis
+                .iter()
+                .filter_map(|(dst, args)| {
+                    args.iter()
+                        .find(|(from, _)| *from == predecessor)
+                        .map(|(_, src)| (*dst, *src))
+                })
+                .collect();
+            let copies: Vec<L::Instruction> = sequentialize(copies, &mut fresh)
+                .into_iter()
+                .map(|(dst, src)| L::copy(dst, src))
+                .collect();
+            if copies.is_empty() {
+                continue;
+            }
+
+            let block = blocks
+                .get_mut(&predecessor)
+                .expect("Every label has a block");
+            let mut successors = block.successors();
+            successors.sort_by_key(|successor| successor.0);
+            successors.dedup();
+
+            // Copies at the end of the block still happen before its exit, so they can't go
+            //   there if the exit reads or writes anything.
+            let exit_clear =
+                L::exit_uses(&block.exit).is_empty() && L::exit_defs(&block.exit).is_empty();
+            if successors.len() == 1 && exit_clear {
+                block.code.extend(copies);
+                continue;
+            }
+
+            // The entry can also be come into from outside the graph, so it never has only the
+            //   one way in.
+            if label != entry && graph.predecessors(label).len() == 1 {
+                blocks
+                    .get_mut(&label)
+                    .expect("Every label has a block")
+                    .code
+                    .splice(0..0, copies);
+                continue;
+            }
+
+            // That leaves critical edges, and edges out of a block whose exit is in the way.
+            //   Anything the edge passed along goes on to the block through the split.
+            let split = graph.fresh_label();
+            let (exit, split_exit) = L::split_edge(&block.exit, label, split);
+            block.exit = exit;
+            blocks.insert(
+                split,
+                BasicBlock::new(L::label_entry(split), copies, split_exit),
+            );
+        }
+    }
+
+    Graph::from_blocks(blocks.into_values().collect())
+}
+
+// Orders a set of copies that are all meant to happen at once, so that nothing gets
+//   overwritten before it's read, as (dst, src) pairs.
+fn sequentialize<V, F>(copies: Vec<(V, V)>, fresh: &mut F) -> Vec<(V, V)>
+where
+    V: Copy + Eq,
+    F: FnMut(V) -> V,
+{
+    let mut pending: Vec<(V, V)> = copies.into_iter().filter(|(dst, src)| dst != src).collect();
+    let mut output = vec![];
+
+    while !pending.is_empty() {
+        // Anything that nothing else still needs to read can be written straight away.
+        let ready = (0..pending.len())
+            .find(|&index| pending.iter().all(|(_, src)| *src != pending[index].0));
+        if let Some(index) = ready {
+            output.push(pending.remove(index));
+            continue;
+        }
+
+        // Everything left is in a cycle, so we save one of the destinations off to the side
+        //   first, and read it from there instead.
+        let dst = pending[0].0;
+        let temp = fresh(dst);
+        output.push((temp, dst));
+        for copy in &mut pending {
+            if copy.1 == dst {
+                copy.1 = temp;
+            }
+        }
+    }
+
+    output
+}
+
+struct Phi<V> {
+    // The variable before renaming, and what it's been renamed to in this block.
+    var: V,
+    dst: V,
+    args: PhiArgs<V>,
+}
+
+struct Renamer<'a, L: Ssa, F> {
+    graph: &'a Graph<L>,
+    tree: &'a DominatorTree,
+    fresh: &'a mut F,
+
+    // What each original variable is currently called, innermost last.
+    stacks: FnvHashMap<L::Var, Vec<L::Var>>,
+    phis: FnvHashMap<Label, Vec<Phi<L::Var>>>,
+    blocks: FnvHashMap<Label, BasicBlock<L>>,
+}
+
+impl<'a, L, F> Renamer<'a, L, F>
+where
+    L: Ssa,
+    F: FnMut(L::Var) -> L::Var,
+{
+    fn current(&self, var: L::Var) -> L::Var {
+        self.stacks
+            .get(&var)
+            .and_then(|stack| stack.last().cloned())
+            .unwrap_or(var)
+    }
+
+    fn define(&mut self, var: L::Var, pushed: &mut Vec<L::Var>) -> L::Var {
+        let new = (self.fresh)(var);
+        self.stacks.entry(var).or_default().push(new);
+        pushed.push(var);
+        new
+    }
+
+    // Renames a block, and then everything it immediately dominates, with the names it
+    //   assigned in scope.
+    fn rename(&mut self, label: Label) {
+        let graph = self.graph;
+        let block = &graph[label];
+        let mut pushed = vec![];
+
+        let mut phis = self.phis.remove(&label).unwrap_or_default();
+        for phi in &mut phis {
+            phi.dst = self.define(phi.var, &mut pushed);
+        }
+        self.phis.insert(label, phis);
+
+        let mut code = vec![];
+        for instruction in &block.code {
+            let uses = self.names(L::instruction_uses(instruction));
+            let mut defs = FnvHashMap::default();
+            for var in L::instruction_defs(instruction) {
+                defs.insert(var, self.define(var, &mut pushed));
+            }
+            code.push(L::rename_instruction(
+                instruction,
+                &|var| uses.get(&var).cloned().unwrap_or(var),
+                &|var| defs.get(&var).cloned().unwrap_or(var),
+            ));
+        }
+
+        let uses = self.names(L::exit_uses(&block.exit));
+        let mut defs = FnvHashMap::default();
+        for var in L::exit_defs(&block.exit) {
+            defs.insert(var, self.define(var, &mut pushed));
+        }
+        let exit = L::rename_exit(
+            &block.exit,
+            &|var| uses.get(&var).cloned().unwrap_or(var),
+            &|var| defs.get(&var).cloned().unwrap_or(var),
+        );
+
+        let successors: FnvHashSet<Label> = block.successors().into_iter().collect();
+        for successor in successors {
+            let mut phis = self.phis.remove(&successor).unwrap_or_default();
+            for phi in &mut phis {
+                phi.args.push((label, self.current(phi.var)));
+            }
+            self.phis.insert(successor, phis);
+        }
+
+        self.blocks
+            .insert(label, BasicBlock::new(block.entry.clone(), code, exit));
+
+        let tree = self.tree;
+        for child in tree.children(label) {
+            self.rename(*child);
+        }
+
+        for var in pushed {
+            if let Some(stack) = self.stacks.get_mut(&var) {
+                stack.pop();
+            }
+        }
+    }
+
+    fn names(&self, vars: Vec<L::Var>) -> FnvHashMap<L::Var, L::Var> {
+        vars.into_iter()
+            .map(|var| (var, self.current(var)))
+            .collect()
+    }
+}
@@ -0,0 +1,2665 @@
+pub mod dataflow;
+
+#[cfg(test)]
+mod test {
+    use crate::dataflow::control_dependence;
+    use crate::dataflow::critical_edges;
+    use crate::dataflow::dominator;
+    use crate::dataflow::irreducible;
+    use crate::dataflow::liveness;
+    use crate::dataflow::loops;
+    use crate::dataflow::scc;
+    use crate::dataflow::ssa;
+    use crate::dataflow::*;
+    use fnv::{FnvHashMap, FnvHashSet};
+    use std::collections::HashMap;
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    struct Var(u16);
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    struct Constant(usize);
+
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, Debug, Hash)]
+    enum Arith {
+        Add,
+        Sub,
+        And,
+        Or,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Copy, Clone, Debug, Hash)]
+    enum Cond {
+        Eq,
+        Neq,
+        Lt,
+        Lte,
+    }
+
+    #[derive(Clone, Debug, Hash)]
+    enum RiscEntry {
+        Label(Label),
+        Params(Label, Vec<Var>),
+    }
+
+    #[derive(Clone, Debug, Hash)]
+    enum RiscInstruction {
+        Load(Var, Constant),
+        Arith(Arith, Var, Var, Var),
+        Phi(Var, Vec<(Label, Var)>),
+        Move(Var, Var),
+    }
+
+    #[derive(Clone, Debug, Hash)]
+    enum RiscExit {
+        Cond(Cond, Var, Var, Label, Label),
+        Jump(Label),
+        JumpWith(Label, Vec<Var>),
+        CondWith(Cond, Var, Var, (Label, Vec<Var>), (Label, Vec<Var>)),
+        Ret,
+    }
+
+    impl Entry for RiscEntry {
+        fn label(&self) -> Label {
+            match self {
+                RiscEntry::Label(l) | RiscEntry::Params(l, _) => *l,
+            }
+        }
+    }
+
+    impl Instruction for RiscInstruction {}
+
+    impl Exit for RiscExit {
+        fn successors(&self) -> Vec<Label> {
+            match self {
+                RiscExit::Cond(_, _, _, l1, l2) => vec![*l1, *l2],
+                RiscExit::Jump(l) | RiscExit::JumpWith(l, _) => vec![*l],
+                RiscExit::CondWith(_, _, _, (l1, _), (l2, _)) => vec![*l1, *l2],
+                RiscExit::Ret => vec![],
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    struct RiscLanguage;
+
+    impl Language for RiscLanguage {
+        type Entry = RiscEntry;
+        type Instruction = RiscInstruction;
+        type Exit = RiscExit;
+    }
+
+    impl DefUse for RiscLanguage {
+        type Var = Var;
+
+        fn instruction_uses(instruction: &RiscInstruction) -> Vec<Var> {
+            match instruction {
+                RiscInstruction::Load(_, _) => vec![],
+                RiscInstruction::Arith(_, _, src1, src2) => vec![*src1, *src2],
+                RiscInstruction::Phi(_, args) => args.iter().map(|(_, var)| *var).collect(),
+                RiscInstruction::Move(_, src) => vec![*src],
+            }
+        }
+
+        fn instruction_defs(instruction: &RiscInstruction) -> Vec<Var> {
+            match instruction {
+                RiscInstruction::Load(dst, _)
+                | RiscInstruction::Arith(_, dst, _, _)
+                | RiscInstruction::Phi(dst, _)
+                | RiscInstruction::Move(dst, _) => vec![*dst],
+            }
+        }
+
+        fn exit_uses(exit: &RiscExit) -> Vec<Var> {
+            match exit {
+                RiscExit::Cond(_, src1, src2, _, _) => vec![*src1, *src2],
+                RiscExit::JumpWith(_, args) => args.clone(),
+                RiscExit::CondWith(_, src1, src2, (_, args1), (_, args2)) => {
+                    let mut uses = vec![*src1, *src2];
+                    uses.extend(args1.iter().chain(args2));
+                    uses
+                }
+                RiscExit::Jump(_) | RiscExit::Ret => vec![],
+            }
+        }
+    }
+
+    impl BlockParams for RiscLanguage {
+        fn entry_params(entry: &RiscEntry) -> Vec<Var> {
+            match entry {
+                RiscEntry::Params(_, params) => params.clone(),
+                RiscEntry::Label(_) => vec![],
+            }
+        }
+
+        fn exit_args(exit: &RiscExit, successor: Label) -> Vec<Var> {
+            match exit {
+                RiscExit::JumpWith(l, args) if *l == successor => args.clone(),
+                RiscExit::CondWith(_, _, _, (l1, args), _) if *l1 == successor => args.clone(),
+                RiscExit::CondWith(_, _, _, _, (l2, args)) if *l2 == successor => args.clone(),
+                _ => vec![],
+            }
+        }
+    }
+
+    impl ControlFlow for RiscLanguage {
+        fn label_entry(label: Label) -> RiscEntry {
+            RiscEntry::Label(label)
+        }
+
+        fn relabel_entry(entry: &RiscEntry, label: Label) -> RiscEntry {
+            match entry {
+                RiscEntry::Label(_) => RiscEntry::Label(label),
+                RiscEntry::Params(_, params) => RiscEntry::Params(label, params.clone()),
+            }
+        }
+
+        fn jump(target: Label) -> RiscExit {
+            RiscExit::Jump(target)
+        }
+
+        fn map_successors(exit: &RiscExit, f: &dyn Fn(Label) -> Label) -> RiscExit {
+            match exit {
+                RiscExit::Cond(cond, src1, src2, l1, l2) => {
+                    RiscExit::Cond(*cond, *src1, *src2, f(*l1), f(*l2))
+                }
+                RiscExit::Jump(l) => RiscExit::Jump(f(*l)),
+                RiscExit::JumpWith(l, args) => RiscExit::JumpWith(f(*l), args.clone()),
+                RiscExit::CondWith(cond, src1, src2, (l1, args1), (l2, args2)) => {
+                    RiscExit::CondWith(
+                        *cond,
+                        *src1,
+                        *src2,
+                        (f(*l1), args1.clone()),
+                        (f(*l2), args2.clone()),
+                    )
+                }
+                RiscExit::Ret => RiscExit::Ret,
+            }
+        }
+
+        // The arguments for the split edge go on the new block, which jumps on with them.
+        fn split_edge(exit: &RiscExit, successor: Label, split: Label) -> (RiscExit, RiscExit) {
+            match exit {
+                RiscExit::CondWith(cond, src1, src2, edge1, edge2) => {
+                    let args = RiscLanguage::exit_args(exit, successor);
+                    let split_off = |(label, args): &(Label, Vec<Var>)| {
+                        if *label == successor {
+                            (split, vec![])
+                        } else {
+                            (*label, args.clone())
+                        }
+                    };
+                    let exit =
+                        RiscExit::CondWith(*cond, *src1, *src2, split_off(edge1), split_off(edge2));
+                    (exit, RiscExit::JumpWith(successor, args))
+                }
+                RiscExit::JumpWith(_, args) => (
+                    RiscExit::Jump(split),
+                    RiscExit::JumpWith(successor, args.clone()),
+                ),
+                exit => {
+                    let exit = RiscLanguage::map_successors(exit, &|label| {
+                        if label == successor {
+                            split
+                        } else {
+                            label
+                        }
+                    });
+                    (exit, RiscExit::Jump(successor))
+                }
+            }
+        }
+    }
+
+    impl ssa::Ssa for RiscLanguage {
+        fn phi(dst: Var, args: ssa::PhiArgs<Var>) -> RiscInstruction {
+            RiscInstruction::Phi(dst, args)
+        }
+
+        fn as_phi(instruction: &RiscInstruction) -> Option<(Var, &ssa::PhiArgs<Var>)> {
+            match instruction {
+                RiscInstruction::Phi(dst, args) => Some((*dst, args)),
+                _ => None,
+            }
+        }
+
+        fn copy(dst: Var, src: Var) -> RiscInstruction {
+            RiscInstruction::Move(dst, src)
+        }
+
+        fn rename_instruction(
+            instruction: &RiscInstruction,
+            uses: &dyn Fn(Var) -> Var,
+            defs: &dyn Fn(Var) -> Var,
+        ) -> RiscInstruction {
+            match instruction {
+                RiscInstruction::Load(dst, constant) => {
+                    RiscInstruction::Load(defs(*dst), *constant)
+                }
+                RiscInstruction::Arith(arith, dst, src1, src2) => {
+                    RiscInstruction::Arith(*arith, defs(*dst), uses(*src1), uses(*src2))
+                }
+                RiscInstruction::Phi(dst, args) => RiscInstruction::Phi(
+                    defs(*dst),
+                    args.iter()
+                        .map(|(label, var)| (*label, uses(*var)))
+                        .collect(),
+                ),
+                RiscInstruction::Move(dst, src) => RiscInstruction::Move(defs(*dst), uses(*src)),
+            }
+        }
+
+        fn rename_exit(
+            exit: &RiscExit,
+            uses: &dyn Fn(Var) -> Var,
+            _defs: &dyn Fn(Var) -> Var,
+        ) -> RiscExit {
+            match exit {
+                RiscExit::Cond(cond, src1, src2, l1, l2) => {
+                    RiscExit::Cond(*cond, uses(*src1), uses(*src2), *l1, *l2)
+                }
+                RiscExit::JumpWith(l, args) => {
+                    RiscExit::JumpWith(*l, args.iter().map(|arg| uses(*arg)).collect())
+                }
+                RiscExit::CondWith(cond, src1, src2, (l1, args1), (l2, args2)) => {
+                    RiscExit::CondWith(
+                        *cond,
+                        uses(*src1),
+                        uses(*src2),
+                        (*l1, args1.iter().map(|arg| uses(*arg)).collect()),
+                        (*l2, args2.iter().map(|arg| uses(*arg)).collect()),
+                    )
+                }
+                exit => exit.clone(),
+            }
+        }
+    }
+
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    enum WithTop<T> {
+        Top,
+        Elem(T),
+    }
+
+    #[derive(Clone, Debug)]
+    struct ConstFact {
+        vars: HashMap<Var, WithTop<Constant>>,
+    }
+
+    impl ConstFact {
+        fn new() -> ConstFact {
+            ConstFact {
+                vars: HashMap::new(),
+            }
+        }
+
+        fn set(&mut self, var: Var, constant: Constant) {
+            self.vars.insert(var, WithTop::Elem(constant));
+        }
+
+        fn get(&self, var: Var) -> Option<WithTop<Constant>> {
+            self.vars.get(&var).cloned()
+        }
+
+        fn get_const(&self, var: Var) -> Option<Constant> {
+            match self.vars.get(&var) {
+                Some(WithTop::Elem(c)) => Some(*c),
+                _ => None,
+            }
+        }
+    }
+
+    impl Lattice for ConstFact {
+        fn bottom() -> Self {
+            ConstFact::new()
+        }
+
+        fn join(&mut self, other: &Self, _label: Label) -> bool {
+            let mut changed = false;
+            for (k, v) in &other.vars {
+                let old = self.get(*k);
+                let new = match (old, v) {
+                    (Some(WithTop::Top), _) => WithTop::Top,
+                    (_, WithTop::Top) => WithTop::Top,
+                    (Some(WithTop::Elem(x)), WithTop::Elem(y)) => {
+                        if x == *y {
+                            WithTop::Elem(x)
+                        } else {
+                            WithTop::Top
+                        }
+                    }
+                    (None, anything) => *anything,
+                };
+
+                if old != Some(new) {
+                    changed = true;
+                    self.vars.insert(*k, new);
+                }
+            }
+
+            changed
+        }
+    }
+
+    struct ConstantPropagation;
+
+    impl ForwardAnalysis<RiscLanguage, ConstFact> for ConstantPropagation {
+        fn analyze_entry(
+            &mut self,
+            _graph: &Graph<RiscLanguage>,
+            _label: Label,
+            _entry: &RiscEntry,
+            fact: ConstFact,
+        ) -> ConstFact {
+            fact
+        }
+
+        fn analyze_entry_edges(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            _entry: &RiscEntry,
+            incoming: &FactBase<ConstFact>,
+            mut fact: ConstFact,
+        ) -> ConstFact {
+            // A phi takes its value from whichever block we came in from, so it only needs to
+            //   agree across the predecessors we've actually seen.
+            for instruction in &graph[label].code {
+                let RiscInstruction::Phi(dst, args) = instruction else {
+                    break;
+                };
+                let mut value = None;
+                for (predecessor, arg) in args {
+                    if let Some(from) = incoming.get(predecessor) {
+                        let arg_value = from.get(*arg).unwrap_or(WithTop::Top);
+                        value = match value {
+                            Some(value) if value != arg_value => Some(WithTop::Top),
+                            _ => Some(arg_value),
+                        };
+                    }
+                }
+                fact.vars.insert(*dst, value.unwrap_or(WithTop::Top));
+            }
+            fact
+        }
+
+        fn analyze_instruction(
+            &mut self,
+            _graph: &Graph<RiscLanguage>,
+            _label: Label,
+            instruction: &RiscInstruction,
+            analyze: AnalyzeInstruction<ConstFact>,
+        ) -> Option<RewriteInstruction<RiscLanguage>> {
+            match instruction {
+                RiscInstruction::Load(var, constant) => {
+                    analyze.fact_mut().set(*var, *constant);
+                    None
+                }
+                // Already worked out on the way into the block, see analyze_entry_edges.
+                RiscInstruction::Phi(_, _) => None,
+                RiscInstruction::Move(dst, src) => {
+                    let fact = analyze.fact_mut();
+                    let value = fact.get(*src).unwrap_or(WithTop::Top);
+                    fact.vars.insert(*dst, value);
+                    None
+                }
+                RiscInstruction::Arith(arith, dst, src1, src2) => {
+                    let facts = analyze.fact();
+
+                    if let (Some(Constant(c1)), Some(Constant(c2))) =
+                        (facts.get_const(*src1), facts.get_const(*src2))
+                    {
+                        let result = match arith {
+                            Arith::Add => c1 + c2,
+                            Arith::Sub => c1 - c2,
+                            Arith::And => c1 & c2,
+                            Arith::Or => c1 | c2,
+                        };
+
+                        // Out of fuel, so the instruction stays, but we still know what it
+                        //   computes.
+                        if !analyze.can_rewrite() {
+                            analyze.fact_mut().set(*dst, Constant(result));
+                            return None;
+                        }
+                        return Some(
+                            analyze.replace(RiscInstruction::Load(*dst, Constant(result))),
+                        );
+                    }
+                    None
+                }
+            }
+        }
+
+        fn analyze_exit(
+            &mut self,
+            _graph: &Graph<RiscLanguage>,
+            _label: Label,
+            exit: &RiscExit,
+            analyze: AnalyzeExit<ConstFact>,
+        ) -> RewriteExit<RiscLanguage, ConstFact> {
+            let fact = analyze.fact();
+            let mut facts = FnvHashMap::default();
+
+            match exit {
+                RiscExit::Cond(_, _, _, l1, l2) | RiscExit::CondWith(_, _, _, (l1, _), (l2, _)) => {
+                    facts.insert(*l1, fact.clone());
+                    facts.insert(*l2, fact.clone());
+
+                    RewriteExit::Done(facts)
+                }
+                RiscExit::Jump(l1) | RiscExit::JumpWith(l1, _) => {
+                    facts.insert(*l1, fact.clone());
+
+                    RewriteExit::Done(facts)
+                }
+                RiscExit::Ret => RewriteExit::Done(facts),
+            }
+        }
+
+        fn analyze_edge(
+            &mut self,
+            _graph: &Graph<RiscLanguage>,
+            _label: Label,
+            exit: &RiscExit,
+            successor: &RiscEntry,
+            mut fact: ConstFact,
+        ) -> ConstFact {
+            // Read every argument before binding any parameter, since a block can pass its
+            //   own parameters back to itself in a different order.
+            let values: Vec<_> = RiscLanguage::edge_bindings(exit, successor)
+                .into_iter()
+                .map(|(param, arg)| (param, fact.get(arg).unwrap_or(WithTop::Top)))
+                .collect();
+            for (param, value) in values {
+                fact.vars.insert(param, value);
+            }
+            fact
+        }
+    }
+
+    #[test]
+    fn constant_test() {
+        let entry = Label(0);
+        let loop_body = Label(1);
+        let exit = Label(2);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![
+                RiscInstruction::Load(Var(0), Constant(0)),
+                RiscInstruction::Load(Var(1), Constant(1)),
+                RiscInstruction::Load(Var(2), Constant(5)),
+            ],
+            RiscExit::Jump(loop_body),
+        );
+
+        let block1 = BasicBlock::new(
+            RiscEntry::Label(loop_body),
+            vec![RiscInstruction::Arith(Arith::Sub, Var(2), Var(2), Var(1))],
+            RiscExit::Cond(Cond::Eq, Var(2), Var(0), exit, loop_body),
+        );
+
+        let block2 = BasicBlock::new(RiscEntry::Label(exit), vec![], RiscExit::Ret);
+
+        let graph = Graph::from_blocks(vec![block0, block1, block2]);
+
+        let mut analysis = ConstantPropagation;
+        // Strictly speaking, we'd want an entry fact that had all vars as Top..
+        let fact_base = forward_analysis(&mut analysis, &graph, entry, ConstFact::bottom());
+        println!("{:?}", fact_base);
+    }
+
+    #[test]
+    fn constant_rewrite_test() {
+        let entry = Label(0);
+        let loop_body = Label(1);
+        let exit = Label(2);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![
+                RiscInstruction::Load(Var(0), Constant(0)),
+                RiscInstruction::Load(Var(1), Constant(1)),
+                RiscInstruction::Load(Var(3), Constant(2)),
+                RiscInstruction::Arith(Arith::Add, Var(2), Var(3), Var(3)),
+            ],
+            RiscExit::Jump(loop_body),
+        );
+
+        let block1 = BasicBlock::new(
+            RiscEntry::Label(loop_body),
+            vec![RiscInstruction::Arith(Arith::Sub, Var(2), Var(2), Var(1))],
+            RiscExit::Cond(Cond::Eq, Var(2), Var(0), exit, loop_body),
+        );
+
+        let block2 = BasicBlock::new(RiscEntry::Label(exit), vec![], RiscExit::Ret);
+
+        let graph = Graph::from_blocks(vec![block0, block1, block2]);
+
+        let mut analysis = ConstantPropagation;
+        let result = analyze_and_rewrite_forward(
+            &mut analysis,
+            &graph,
+            entry,
+            ConstFact::bottom(),
+            Fuel::unlimited(),
+        );
+
+        // The add only sees constants, so it gets folded away.
+        assert!(matches!(
+            result.graph[entry].code[3],
+            RiscInstruction::Load(Var(2), Constant(4))
+        ));
+
+        // The subtraction was folded while we still thought Var(2) was 4, but the loop
+        //   makes it unknown by the time we reach the fixed point.
+        assert!(matches!(
+            result.graph[loop_body].code[0],
+            RiscInstruction::Arith(Arith::Sub, Var(2), Var(2), Var(1))
+        ));
+        assert_eq!(result.facts[&exit].get(Var(2)), Some(WithTop::Top));
+        assert_eq!(result.facts[&exit].get_const(Var(1)), Some(Constant(1)));
+    }
+
+    #[test]
+    fn fuel_test() {
+        let entry = Label(0);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![
+                RiscInstruction::Load(Var(0), Constant(1)),
+                RiscInstruction::Arith(Arith::Add, Var(1), Var(0), Var(0)),
+                RiscInstruction::Arith(Arith::Add, Var(2), Var(1), Var(1)),
+                RiscInstruction::Arith(Arith::Add, Var(3), Var(2), Var(2)),
+            ],
+            RiscExit::Ret,
+        );
+
+        let graph = Graph::from_blocks(vec![block0]);
+
+        let result = analyze_and_rewrite_forward(
+            &mut ConstantPropagation,
+            &graph,
+            entry,
+            ConstFact::bottom(),
+            Fuel::limited(2),
+        );
+
+        // Only the first two adds get folded, the third is left as it was.
+        assert_eq!(result.fuel_used, 2);
+        assert!(matches!(
+            result.graph[entry].code[..],
+            [
+                RiscInstruction::Load(Var(0), Constant(1)),
+                RiscInstruction::Load(Var(1), Constant(2)),
+                RiscInstruction::Load(Var(2), Constant(4)),
+                RiscInstruction::Arith(Arith::Add, Var(3), Var(2), Var(2)),
+            ]
+        ));
+
+        let unlimited = analyze_and_rewrite_forward(
+            &mut ConstantPropagation,
+            &graph,
+            entry,
+            ConstFact::bottom(),
+            Fuel::unlimited(),
+        );
+        assert_eq!(unlimited.fuel_used, 3);
+    }
+
+    // Swaps the operands of every add, which never stops finding something to rewrite. Anything
+    //   it doesn't swap is left to constant propagation.
+    struct SwapAdds(RewriteDepth);
+
+    impl ForwardAnalysis<RiscLanguage, ConstFact> for SwapAdds {
+        fn analyze_entry(
+            &mut self,
+            _graph: &Graph<RiscLanguage>,
+            _label: Label,
+            _entry: &RiscEntry,
+            fact: ConstFact,
+        ) -> ConstFact {
+            fact
+        }
+
+        fn analyze_instruction(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            instruction: &RiscInstruction,
+            analyze: AnalyzeInstruction<ConstFact>,
+        ) -> Option<RewriteInstruction<RiscLanguage>> {
+            match instruction {
+                RiscInstruction::Arith(Arith::Add, dst, src1, src2) if analyze.can_rewrite() => {
+                    Some(analyze.replace(RiscInstruction::Arith(Arith::Add, *dst, *src2, *src1)))
+                }
+                _ => ConstantPropagation.analyze_instruction(graph, label, instruction, analyze),
+            }
+        }
+
+        fn analyze_exit(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            exit: &RiscExit,
+            analyze: AnalyzeExit<ConstFact>,
+        ) -> RewriteExit<RiscLanguage, ConstFact> {
+            ConstantPropagation.analyze_exit(graph, label, exit, analyze)
+        }
+
+        fn rewrite_depth(&self) -> RewriteDepth {
+            self.0
+        }
+    }
+
+    // Constant propagation that goes on to rewrite what's already been rewritten.
+    struct DeepConstants;
+
+    impl ForwardAnalysis<RiscLanguage, ConstFact> for DeepConstants {
+        fn analyze_entry(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            entry: &RiscEntry,
+            fact: ConstFact,
+        ) -> ConstFact {
+            ConstantPropagation.analyze_entry(graph, label, entry, fact)
+        }
+
+        fn analyze_instruction(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            instruction: &RiscInstruction,
+            analyze: AnalyzeInstruction<ConstFact>,
+        ) -> Option<RewriteInstruction<RiscLanguage>> {
+            ConstantPropagation.analyze_instruction(graph, label, instruction, analyze)
+        }
+
+        fn analyze_exit(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            exit: &RiscExit,
+            analyze: AnalyzeExit<ConstFact>,
+        ) -> RewriteExit<RiscLanguage, ConstFact> {
+            ConstantPropagation.analyze_exit(graph, label, exit, analyze)
+        }
+
+        fn rewrite_depth(&self) -> RewriteDepth {
+            RewriteDepth::Deep(1)
+        }
+    }
+
+    #[test]
+    fn rewrite_depth_test() {
+        let entry = Label(0);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![RiscInstruction::Arith(Arith::Add, Var(2), Var(0), Var(1))],
+            RiscExit::Ret,
+        );
+
+        let graph = Graph::from_blocks(vec![block0]);
+
+        let rewrite = |depth| {
+            analyze_and_rewrite_forward(
+                &mut SwapAdds(depth),
+                &graph,
+                entry,
+                ConstFact::bottom(),
+                Fuel::unlimited(),
+            )
+        };
+
+        // Shallow rewriting swaps the add once and leaves it there.
+        let shallow = rewrite(RewriteDepth::Shallow);
+        assert_eq!(shallow.fuel_used, 1);
+        assert!(matches!(
+            shallow.graph[entry].code[..],
+            [RiscInstruction::Arith(Arith::Add, Var(2), Var(1), Var(0))]
+        ));
+
+        // Going one deeper swaps it back again, and then stops.
+        let deep = rewrite(RewriteDepth::Deep(1));
+        assert_eq!(deep.fuel_used, 2);
+        assert!(matches!(
+            deep.graph[entry].code[..],
+            [RiscInstruction::Arith(Arith::Add, Var(2), Var(0), Var(1))]
+        ));
+    }
+
+    #[test]
+    fn pair_test() {
+        let entry = Label(0);
+        let loop_body = Label(1);
+        let exit = Label(2);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![
+                RiscInstruction::Load(Var(0), Constant(0)),
+                RiscInstruction::Load(Var(1), Constant(1)),
+                RiscInstruction::Arith(Arith::Add, Var(2), Var(1), Var(1)),
+            ],
+            RiscExit::Jump(loop_body),
+        );
+
+        let block1 = BasicBlock::new(
+            RiscEntry::Label(loop_body),
+            vec![],
+            RiscExit::Cond(Cond::Eq, Var(2), Var(0), exit, loop_body),
+        );
+
+        let block2 = BasicBlock::new(RiscEntry::Label(exit), vec![], RiscExit::Ret);
+
+        let graph = Graph::from_blocks(vec![block0, block1, block2]);
+
+        let mut analysis = Pair(ConstantPropagation, dominator::DominatorAnalysis);
+        let result = analyze_and_rewrite_forward(
+            &mut analysis,
+            &graph,
+            entry,
+            Lattice::bottom(),
+            Fuel::unlimited(),
+        );
+
+        // Both halves of the pair get worked out in the one fixed point, and constant
+        //   propagation still gets to rewrite.
+        assert!(matches!(
+            result.graph[entry].code[2],
+            RiscInstruction::Load(Var(2), Constant(2))
+        ));
+        let (constants, dominators) = &result.facts[&exit];
+        assert_eq!(constants.get_const(Var(2)), Some(Constant(2)));
+        assert_eq!(dominators.dominates, Some(vec![entry, loop_body]));
+    }
+
+    #[test]
+    fn or_else_then_test() {
+        let entry = Label(0);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![
+                RiscInstruction::Load(Var(0), Constant(1)),
+                RiscInstruction::Load(Var(1), Constant(2)),
+                RiscInstruction::Arith(Arith::Add, Var(2), Var(0), Var(1)),
+                RiscInstruction::Arith(Arith::Add, Var(4), Var(3), Var(0)),
+            ],
+            RiscExit::Ret,
+        );
+
+        let graph = Graph::from_blocks(vec![block0]);
+
+        // Constant propagation folds what it can, and anything else gets swapped instead.
+        let mut analysis = OrElse(ConstantPropagation, SwapAdds(RewriteDepth::Shallow));
+        let result = analyze_and_rewrite_forward(
+            &mut analysis,
+            &graph,
+            entry,
+            ConstFact::bottom(),
+            Fuel::unlimited(),
+        );
+        assert!(matches!(
+            result.graph[entry].code[2..],
+            [
+                RiscInstruction::Load(Var(2), Constant(3)),
+                RiscInstruction::Arith(Arith::Add, Var(4), Var(0), Var(3)),
+            ]
+        ));
+
+        // Swapping first means constant propagation never gets a look in, unless it's handed
+        //   what the swap produced.
+        let mut analysis = OrElse(SwapAdds(RewriteDepth::Shallow), ConstantPropagation);
+        let result = analyze_and_rewrite_forward(
+            &mut analysis,
+            &graph,
+            entry,
+            ConstFact::bottom(),
+            Fuel::unlimited(),
+        );
+        assert!(matches!(
+            result.graph[entry].code[2],
+            RiscInstruction::Arith(Arith::Add, Var(2), Var(1), Var(0))
+        ));
+
+        // What the swap produced is a rewrite deep, so at Shallow Then is no different.
+        let mut analysis = Then(SwapAdds(RewriteDepth::Shallow), ConstantPropagation);
+        let result = analyze_and_rewrite_forward(
+            &mut analysis,
+            &graph,
+            entry,
+            ConstFact::bottom(),
+            Fuel::unlimited(),
+        );
+        assert!(matches!(
+            result.graph[entry].code[2],
+            RiscInstruction::Arith(Arith::Add, Var(2), Var(1), Var(0))
+        ));
+
+        // Going a rewrite deeper lets constant propagation fold the swapped add, which burns
+        //   fuel of its own.
+        let then = || Then(SwapAdds(RewriteDepth::Deep(1)), DeepConstants);
+        let result = analyze_and_rewrite_forward(
+            &mut then(),
+            &graph,
+            entry,
+            ConstFact::bottom(),
+            Fuel::unlimited(),
+        );
+        assert!(matches!(
+            result.graph[entry].code[2],
+            RiscInstruction::Load(Var(2), Constant(3))
+        ));
+
+        // Without the fuel for both, the swap goes in on its own.
+        let result = analyze_and_rewrite_forward(
+            &mut then(),
+            &graph,
+            entry,
+            ConstFact::bottom(),
+            Fuel::limited(1),
+        );
+        assert_eq!(result.fuel_used, 1);
+        assert!(matches!(
+            result.graph[entry].code[2..],
+            [
+                RiscInstruction::Arith(Arith::Add, Var(2), Var(1), Var(0)),
+                RiscInstruction::Arith(Arith::Add, Var(4), Var(3), Var(0)),
+            ]
+        ));
+        let result = analyze_and_rewrite_forward(
+            &mut then(),
+            &graph,
+            entry,
+            ConstFact::bottom(),
+            Fuel::limited(2),
+        );
+        assert_eq!(result.fuel_used, 2);
+        assert!(matches!(
+            result.graph[entry].code[2..],
+            [
+                RiscInstruction::Load(Var(2), Constant(3)),
+                RiscInstruction::Arith(Arith::Add, Var(4), Var(3), Var(0)),
+            ]
+        ));
+    }
+
+    // Lowers an 'or' into a diamond that loads the result on both sides, to exercise sub-graph
+    //   rewrites. Everything else is left to constant propagation.
+    struct LowerOr;
+
+    impl ForwardAnalysis<RiscLanguage, ConstFact> for LowerOr {
+        fn analyze_entry(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            entry: &RiscEntry,
+            fact: ConstFact,
+        ) -> ConstFact {
+            ConstantPropagation.analyze_entry(graph, label, entry, fact)
+        }
+
+        fn analyze_instruction(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            instruction: &RiscInstruction,
+            analyze: AnalyzeInstruction<ConstFact>,
+        ) -> Option<RewriteInstruction<RiscLanguage>> {
+            match instruction {
+                RiscInstruction::Arith(Arith::Or, dst, src1, src2) => {
+                    let sub_graph = Graph::from_blocks(vec![
+                        BasicBlock::new(
+                            RiscEntry::Label(Label(10)),
+                            vec![RiscInstruction::Load(*dst, Constant(1))],
+                            RiscExit::Jump(Label(12)),
+                        ),
+                        BasicBlock::new(
+                            RiscEntry::Label(Label(11)),
+                            vec![RiscInstruction::Load(*dst, Constant(1))],
+                            RiscExit::Jump(Label(12)),
+                        ),
+                    ]);
+                    Some(analyze.replace_with_graph(
+                        RiscExit::Cond(Cond::Eq, *src1, *src2, Label(10), Label(11)),
+                        sub_graph,
+                        RiscEntry::Label(Label(12)),
+                    ))
+                }
+                _ => ConstantPropagation.analyze_instruction(graph, label, instruction, analyze),
+            }
+        }
+
+        fn analyze_exit(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            exit: &RiscExit,
+            analyze: AnalyzeExit<ConstFact>,
+        ) -> RewriteExit<RiscLanguage, ConstFact> {
+            ConstantPropagation.analyze_exit(graph, label, exit, analyze)
+        }
+    }
+
+    #[test]
+    fn splice_instruction_test() {
+        let entry = Label(0);
+        let exit = Label(1);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![
+                RiscInstruction::Load(Var(0), Constant(0)),
+                RiscInstruction::Load(Var(1), Constant(1)),
+                RiscInstruction::Arith(Arith::Or, Var(2), Var(0), Var(1)),
+                RiscInstruction::Arith(Arith::Add, Var(3), Var(2), Var(2)),
+            ],
+            RiscExit::Jump(exit),
+        );
+        let block1 = BasicBlock::new(RiscEntry::Label(exit), vec![], RiscExit::Ret);
+        let graph = Graph::from_blocks(vec![block0, block1]);
+
+        let result = analyze_and_rewrite_forward(
+            &mut LowerOr,
+            &graph,
+            entry,
+            ConstFact::bottom(),
+            Fuel::unlimited(),
+        );
+
+        assert_eq!(result.graph[entry].code.len(), 2);
+        assert!(matches!(
+            result.graph[entry].exit,
+            RiscExit::Cond(Cond::Eq, Var(0), Var(1), Label(10), Label(11))
+        ));
+        assert!(result.graph.contains(Label(10)));
+        assert!(result.graph.contains(Label(11)));
+
+        // The rest of the block keeps being analyzed, and rewritten, after the diamond.
+        let rest = &result.graph[Label(12)];
+        assert!(matches!(
+            rest.code[..],
+            [RiscInstruction::Load(Var(3), Constant(2))]
+        ));
+        assert!(matches!(rest.exit, RiscExit::Jump(Label(1))));
+        assert_eq!(
+            result.facts[&Label(12)].get_const(Var(2)),
+            Some(Constant(1))
+        );
+        assert_eq!(result.facts[&exit].get_const(Var(3)), Some(Constant(2)));
+    }
+
+    // LowerOr always splices in blocks 10, 11 and 12, so it can't be used on a graph that
+    //   already has any of those.
+    #[test]
+    #[should_panic(expected = "which is already in the graph")]
+    fn splice_label_collision_test() {
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(Label(0)),
+            vec![RiscInstruction::Arith(Arith::Or, Var(2), Var(0), Var(1))],
+            RiscExit::Jump(Label(11)),
+        );
+        let graph = Graph::from_blocks(vec![block0, ret(11)]);
+
+        analyze_and_rewrite_forward(
+            &mut LowerOr,
+            &graph,
+            Label(0),
+            ConstFact::bottom(),
+            Fuel::unlimited(),
+        );
+    }
+
+    // LowerOr rewrites the Or whether or not it's allowed to, so once the fuel runs out there's
+    //   nothing it can tell us about the Or as it is. That has to be caught rather than leave
+    //   the fact it had before the Or.
+    #[test]
+    #[should_panic(expected = "can_rewrite() allows it")]
+    fn unchecked_rewrite_test() {
+        let entry = Label(0);
+        let exit = Label(1);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![
+                RiscInstruction::Load(Var(0), Constant(0)),
+                RiscInstruction::Load(Var(2), Constant(5)),
+                RiscInstruction::Arith(Arith::Or, Var(2), Var(0), Var(0)),
+            ],
+            RiscExit::Jump(exit),
+        );
+        let graph = Graph::from_blocks(vec![block0, ret(1)]);
+
+        analyze_and_rewrite_forward(
+            &mut LowerOr,
+            &graph,
+            entry,
+            ConstFact::bottom(),
+            Fuel::limited(0),
+        );
+    }
+
+    // The same goes for LowerOr as half of a pair, whose rewrite can't just be dropped either.
+    #[test]
+    #[should_panic(expected = "can_rewrite() allows it")]
+    fn unchecked_pair_rewrite_test() {
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(Label(0)),
+            vec![RiscInstruction::Arith(Arith::Or, Var(2), Var(0), Var(1))],
+            RiscExit::Jump(Label(1)),
+        );
+        let graph = Graph::from_blocks(vec![block0, ret(1)]);
+
+        analyze_and_rewrite_forward(
+            &mut Pair(ConstantPropagation, LowerOr),
+            &graph,
+            Label(0),
+            Lattice::bottom(),
+            Fuel::limited(0),
+        );
+    }
+
+    // Turns a 'less than or equal' into a small decision tree, to exercise exit rewrites.
+    struct LowerLte;
+
+    impl ForwardAnalysis<RiscLanguage, ConstFact> for LowerLte {
+        fn analyze_entry(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            entry: &RiscEntry,
+            fact: ConstFact,
+        ) -> ConstFact {
+            ConstantPropagation.analyze_entry(graph, label, entry, fact)
+        }
+
+        fn analyze_instruction(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            instruction: &RiscInstruction,
+            analyze: AnalyzeInstruction<ConstFact>,
+        ) -> Option<RewriteInstruction<RiscLanguage>> {
+            ConstantPropagation.analyze_instruction(graph, label, instruction, analyze)
+        }
+
+        fn analyze_exit(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            exit: &RiscExit,
+            analyze: AnalyzeExit<ConstFact>,
+        ) -> RewriteExit<RiscLanguage, ConstFact> {
+            match exit {
+                RiscExit::Cond(Cond::Lte, src1, src2, l1, l2) => {
+                    let sub_graph = Graph::from_blocks(vec![BasicBlock::new(
+                        RiscEntry::Label(Label(20)),
+                        vec![],
+                        RiscExit::Cond(Cond::Eq, *src1, *src2, *l1, *l2),
+                    )]);
+                    RewriteExit::Graph(
+                        RiscExit::Cond(Cond::Lt, *src1, *src2, *l1, Label(20)),
+                        sub_graph,
+                    )
+                }
+                _ => ConstantPropagation.analyze_exit(graph, label, exit, analyze),
+            }
+        }
+    }
+
+    #[test]
+    fn splice_exit_test() {
+        let entry = Label(0);
+        let then_branch = Label(1);
+        let else_branch = Label(2);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![RiscInstruction::Load(Var(0), Constant(3))],
+            RiscExit::Cond(Cond::Lte, Var(0), Var(1), then_branch, else_branch),
+        );
+        let block1 = BasicBlock::new(RiscEntry::Label(then_branch), vec![], RiscExit::Ret);
+        let block2 = BasicBlock::new(RiscEntry::Label(else_branch), vec![], RiscExit::Ret);
+        let graph = Graph::from_blocks(vec![block0, block1, block2]);
+
+        let result = analyze_and_rewrite_forward(
+            &mut LowerLte,
+            &graph,
+            entry,
+            ConstFact::bottom(),
+            Fuel::unlimited(),
+        );
+
+        assert!(matches!(
+            result.graph[entry].exit,
+            RiscExit::Cond(Cond::Lt, Var(0), Var(1), Label(1), Label(20))
+        ));
+        assert!(matches!(
+            result.graph[Label(20)].exit,
+            RiscExit::Cond(Cond::Eq, Var(0), Var(1), Label(1), Label(2))
+        ));
+
+        // Facts make it through the decision tree to both of the original successors.
+        for label in &[Label(20), then_branch, else_branch] {
+            assert_eq!(result.facts[label].get_const(Var(0)), Some(Constant(3)));
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct LiveFact {
+        vars: FnvHashSet<Var>,
+    }
+
+    impl Lattice for LiveFact {
+        fn bottom() -> Self {
+            LiveFact {
+                vars: FnvHashSet::default(),
+            }
+        }
+
+        fn join(&mut self, other: &Self, _label: Label) -> bool {
+            let before = self.vars.len();
+            self.vars.extend(other.vars.iter().cloned());
+            before != self.vars.len()
+        }
+    }
+
+    struct Liveness;
+
+    impl BackwardAnalysis<RiscLanguage, LiveFact> for Liveness {
+        fn analyze_exit(
+            &mut self,
+            _graph: &Graph<RiscLanguage>,
+            _label: Label,
+            exit: &RiscExit,
+            analyze: AnalyzeExitBackward<LiveFact>,
+        ) -> Option<RewriteExitBackward<RiscLanguage>> {
+            if let RiscExit::Cond(_, src1, src2, _, _) = exit {
+                let fact = analyze.fact_mut();
+                fact.vars.insert(*src1);
+                fact.vars.insert(*src2);
+            }
+            None
+        }
+
+        fn analyze_instruction(
+            &mut self,
+            _graph: &Graph<RiscLanguage>,
+            _label: Label,
+            instruction: &RiscInstruction,
+            analyze: AnalyzeInstructionBackward<LiveFact>,
+        ) -> Option<RewriteInstructionBackward<RiscLanguage>> {
+            let fact = analyze.fact_mut();
+            match instruction {
+                RiscInstruction::Load(dst, _) => {
+                    fact.vars.remove(dst);
+                }
+                RiscInstruction::Arith(_, dst, src1, src2) => {
+                    fact.vars.remove(dst);
+                    fact.vars.insert(*src1);
+                    fact.vars.insert(*src2);
+                }
+                RiscInstruction::Phi(dst, args) => {
+                    fact.vars.remove(dst);
+                    fact.vars.extend(args.iter().map(|(_, var)| *var));
+                }
+                RiscInstruction::Move(dst, src) => {
+                    fact.vars.remove(dst);
+                    fact.vars.insert(*src);
+                }
+            }
+            None
+        }
+
+        fn analyze_entry(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            _entry: &RiscEntry,
+            fact: LiveFact,
+        ) -> FactBase<LiveFact> {
+            let mut facts = FnvHashMap::default();
+            for &predecessor in graph.predecessors(label) {
+                facts.insert(predecessor, fact.clone());
+            }
+            facts
+        }
+    }
+
+    fn live(vars: &[u16]) -> LiveFact {
+        LiveFact {
+            vars: vars.iter().map(|v| Var(*v)).collect(),
+        }
+    }
+
+    fn jump(from: u32, to: u32) -> BasicBlock<RiscLanguage> {
+        BasicBlock::new(
+            RiscEntry::Label(Label(from)),
+            vec![],
+            RiscExit::Jump(Label(to)),
+        )
+    }
+
+    fn cond(from: u32, to1: u32, to2: u32) -> BasicBlock<RiscLanguage> {
+        BasicBlock::new(
+            RiscEntry::Label(Label(from)),
+            vec![],
+            RiscExit::Cond(Cond::Eq, Var(0), Var(1), Label(to1), Label(to2)),
+        )
+    }
+
+    fn ret(from: u32) -> BasicBlock<RiscLanguage> {
+        BasicBlock::new(RiscEntry::Label(Label(from)), vec![], RiscExit::Ret)
+    }
+
+    #[test]
+    fn liveness_test() {
+        let entry = Label(0);
+        let loop_body = Label(1);
+        let exit = Label(2);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![
+                RiscInstruction::Load(Var(0), Constant(0)),
+                RiscInstruction::Load(Var(1), Constant(1)),
+            ],
+            RiscExit::Jump(loop_body),
+        );
+
+        let block1 = BasicBlock::new(
+            RiscEntry::Label(loop_body),
+            vec![RiscInstruction::Arith(Arith::Sub, Var(2), Var(2), Var(1))],
+            RiscExit::Cond(Cond::Eq, Var(2), Var(0), exit, loop_body),
+        );
+
+        let block2 = BasicBlock::new(RiscEntry::Label(exit), vec![], RiscExit::Ret);
+
+        let graph = Graph::from_blocks(vec![block0, block1, block2]);
+
+        // Facts are what's live on the way out of each block.
+        let facts = backward_analysis(&mut Liveness, &graph, entry, LiveFact::bottom());
+        assert_eq!(facts[&exit], live(&[]));
+        assert_eq!(facts[&loop_body], live(&[0, 1, 2]));
+        assert_eq!(facts[&entry], live(&[0, 1, 2]));
+
+        // Going through DefUse gets the same answer.
+        let generic = backward_analysis(
+            &mut liveness::Liveness,
+            &graph,
+            entry,
+            liveness::LiveVars::bottom(),
+        );
+        for (label, fact) in &facts {
+            assert_eq!(generic[label].vars, fact.vars);
+        }
+
+        let mut boundary = FnvHashMap::default();
+        boundary.insert(exit, live(&[3]));
+        let facts = backward_analysis_with_boundary(&mut Liveness, &graph, entry, boundary);
+        assert_eq!(facts[&exit], live(&[3]));
+        assert_eq!(facts[&loop_body], live(&[0, 1, 2, 3]));
+        assert_eq!(facts[&entry], live(&[0, 1, 2, 3]));
+    }
+
+    // The backward version of LowerLte.
+    struct LowerLteBackward;
+
+    impl BackwardAnalysis<RiscLanguage, LiveFact> for LowerLteBackward {
+        fn analyze_exit(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            exit: &RiscExit,
+            analyze: AnalyzeExitBackward<LiveFact>,
+        ) -> Option<RewriteExitBackward<RiscLanguage>> {
+            match exit {
+                RiscExit::Cond(Cond::Lte, src1, src2, l1, l2) => {
+                    let sub_graph = Graph::from_blocks(vec![BasicBlock::new(
+                        RiscEntry::Label(Label(20)),
+                        vec![],
+                        RiscExit::Cond(Cond::Eq, *src1, *src2, *l1, *l2),
+                    )]);
+                    Some(analyze.replace_with_graph(
+                        RiscExit::Cond(Cond::Lt, *src1, *src2, *l1, Label(20)),
+                        sub_graph,
+                    ))
+                }
+                _ => Liveness.analyze_exit(graph, label, exit, analyze),
+            }
+        }
+
+        fn analyze_instruction(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            instruction: &RiscInstruction,
+            analyze: AnalyzeInstructionBackward<LiveFact>,
+        ) -> Option<RewriteInstructionBackward<RiscLanguage>> {
+            Liveness.analyze_instruction(graph, label, instruction, analyze)
+        }
+
+        fn analyze_entry(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            entry: &RiscEntry,
+            fact: LiveFact,
+        ) -> FactBase<LiveFact> {
+            Liveness.analyze_entry(graph, label, entry, fact)
+        }
+    }
+
+    #[test]
+    fn splice_exit_backward_test() {
+        let entry = Label(0);
+        let compare = Label(1);
+        let then_branch = Label(2);
+        let else_branch = Label(3);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![RiscInstruction::Load(Var(0), Constant(3))],
+            RiscExit::Jump(compare),
+        );
+        let block1 = BasicBlock::new(
+            RiscEntry::Label(compare),
+            vec![],
+            RiscExit::Cond(Cond::Lte, Var(0), Var(1), then_branch, else_branch),
+        );
+        let block2 = BasicBlock::new(
+            RiscEntry::Label(then_branch),
+            vec![RiscInstruction::Arith(Arith::Add, Var(2), Var(4), Var(4))],
+            RiscExit::Ret,
+        );
+        let block3 = BasicBlock::new(
+            RiscEntry::Label(else_branch),
+            vec![RiscInstruction::Arith(Arith::Add, Var(2), Var(5), Var(5))],
+            RiscExit::Ret,
+        );
+        let graph = Graph::from_blocks(vec![block0, block1, block2, block3]);
+
+        let result = analyze_and_rewrite_backward(
+            &mut LowerLteBackward,
+            &graph,
+            entry,
+            LiveFact::bottom(),
+            Fuel::unlimited(),
+        );
+
+        assert!(matches!(
+            result.graph[compare].exit,
+            RiscExit::Cond(Cond::Lt, Var(0), Var(1), Label(2), Label(20))
+        ));
+        assert!(matches!(
+            result.graph[Label(20)].exit,
+            RiscExit::Cond(Cond::Eq, Var(0), Var(1), Label(2), Label(3))
+        ));
+        assert_eq!(result.graph.predecessors(Label(20)), &[compare]);
+        assert_eq!(
+            result.graph.predecessors(then_branch),
+            &[compare, Label(20)]
+        );
+        assert_eq!(result.graph.predecessors(else_branch), &[Label(20)]);
+
+        // Facts come back out of both of the original successors and through the decision
+        //   tree.
+        assert_eq!(result.facts[&Label(20)], live(&[4, 5]));
+        assert_eq!(result.facts[&compare], live(&[0, 1, 4, 5]));
+        assert_eq!(result.facts[&entry], live(&[0, 1, 4, 5]));
+    }
+
+    #[test]
+    #[should_panic(expected = "which is already in the graph")]
+    fn splice_label_collision_backward_test() {
+        let graph = Graph::from_blocks(vec![
+            BasicBlock::new(
+                RiscEntry::Label(Label(0)),
+                vec![],
+                RiscExit::Cond(Cond::Lte, Var(0), Var(1), Label(1), Label(20)),
+            ),
+            ret(1),
+            ret(20),
+        ]);
+
+        backward_analysis(&mut LowerLteBackward, &graph, Label(0), LiveFact::bottom());
+    }
+
+    // The backward version of LowerOr.
+    struct LowerOrBackward;
+
+    impl BackwardAnalysis<RiscLanguage, LiveFact> for LowerOrBackward {
+        fn analyze_exit(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            exit: &RiscExit,
+            analyze: AnalyzeExitBackward<LiveFact>,
+        ) -> Option<RewriteExitBackward<RiscLanguage>> {
+            Liveness.analyze_exit(graph, label, exit, analyze)
+        }
+
+        fn analyze_instruction(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            instruction: &RiscInstruction,
+            analyze: AnalyzeInstructionBackward<LiveFact>,
+        ) -> Option<RewriteInstructionBackward<RiscLanguage>> {
+            match instruction {
+                RiscInstruction::Arith(Arith::Or, dst, src1, src2) => {
+                    let sub_graph = Graph::from_blocks(vec![
+                        BasicBlock::new(
+                            RiscEntry::Label(Label(10)),
+                            vec![RiscInstruction::Load(*dst, Constant(1))],
+                            RiscExit::Jump(Label(12)),
+                        ),
+                        BasicBlock::new(
+                            RiscEntry::Label(Label(11)),
+                            vec![RiscInstruction::Load(*dst, Constant(1))],
+                            RiscExit::Jump(Label(12)),
+                        ),
+                    ]);
+                    Some(analyze.replace_with_graph(
+                        RiscExit::Cond(Cond::Eq, *src1, *src2, Label(10), Label(11)),
+                        sub_graph,
+                        RiscEntry::Label(Label(12)),
+                    ))
+                }
+                _ => Liveness.analyze_instruction(graph, label, instruction, analyze),
+            }
+        }
+
+        fn analyze_entry(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            entry: &RiscEntry,
+            fact: LiveFact,
+        ) -> FactBase<LiveFact> {
+            Liveness.analyze_entry(graph, label, entry, fact)
+        }
+    }
+
+    #[test]
+    fn splice_instruction_backward_test() {
+        let entry = Label(0);
+        let body = Label(1);
+        let exit = Label(2);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![RiscInstruction::Load(Var(4), Constant(4))],
+            RiscExit::Jump(body),
+        );
+        let block1 = BasicBlock::new(
+            RiscEntry::Label(body),
+            vec![
+                RiscInstruction::Load(Var(0), Constant(0)),
+                RiscInstruction::Arith(Arith::Or, Var(2), Var(0), Var(1)),
+                RiscInstruction::Arith(Arith::Add, Var(3), Var(2), Var(4)),
+            ],
+            RiscExit::Jump(exit),
+        );
+        let block2 = BasicBlock::new(
+            RiscEntry::Label(exit),
+            vec![RiscInstruction::Arith(Arith::Add, Var(5), Var(3), Var(3))],
+            RiscExit::Ret,
+        );
+        let graph = Graph::from_blocks(vec![block0, block1, block2]);
+
+        let result = analyze_and_rewrite_backward(
+            &mut LowerOrBackward,
+            &graph,
+            entry,
+            LiveFact::bottom(),
+            Fuel::unlimited(),
+        );
+
+        assert!(matches!(
+            result.graph[body].code[..],
+            [RiscInstruction::Load(Var(0), Constant(0))]
+        ));
+        assert!(matches!(
+            result.graph[body].exit,
+            RiscExit::Cond(Cond::Eq, Var(0), Var(1), Label(10), Label(11))
+        ));
+        let rest = &result.graph[Label(12)];
+        assert!(matches!(
+            rest.code[..],
+            [RiscInstruction::Arith(Arith::Add, Var(3), Var(2), Var(4))]
+        ));
+        assert!(matches!(rest.exit, RiscExit::Jump(Label(2))));
+        assert_eq!(result.graph.predecessors(exit), &[Label(12)]);
+
+        // Liveness flows from the old successor, through the rest of the block and the diamond,
+        //   and into the code left in front of it.
+        assert_eq!(result.facts[&Label(12)], live(&[3]));
+        assert_eq!(result.facts[&Label(10)], live(&[2, 4]));
+        assert_eq!(result.facts[&body], live(&[4]));
+        assert_eq!(result.facts[&entry], live(&[1, 4]));
+    }
+
+    // Removes loads into variables that aren't live afterwards.
+    struct DeadLoads;
+
+    impl BackwardAnalysis<RiscLanguage, LiveFact> for DeadLoads {
+        fn analyze_exit(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            exit: &RiscExit,
+            analyze: AnalyzeExitBackward<LiveFact>,
+        ) -> Option<RewriteExitBackward<RiscLanguage>> {
+            Liveness.analyze_exit(graph, label, exit, analyze)
+        }
+
+        fn analyze_instruction(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            instruction: &RiscInstruction,
+            analyze: AnalyzeInstructionBackward<LiveFact>,
+        ) -> Option<RewriteInstructionBackward<RiscLanguage>> {
+            match instruction {
+                RiscInstruction::Load(dst, _) if !analyze.fact().vars.contains(dst) => {
+                    Some(analyze.replace_many(vec![]))
+                }
+                _ => Liveness.analyze_instruction(graph, label, instruction, analyze),
+            }
+        }
+
+        fn analyze_entry(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            entry: &RiscEntry,
+            fact: LiveFact,
+        ) -> FactBase<LiveFact> {
+            Liveness.analyze_entry(graph, label, entry, fact)
+        }
+    }
+
+    #[test]
+    fn speculative_rewrite_test() {
+        let entry = Label(0);
+        let loop_body = Label(1);
+        let exit = Label(2);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![
+                RiscInstruction::Load(Var(0), Constant(0)),
+                RiscInstruction::Load(Var(5), Constant(9)),
+                RiscInstruction::Load(Var(2), Constant(1)),
+            ],
+            RiscExit::Jump(loop_body),
+        );
+
+        // Var(2) is only read on the next trip around the loop, so the first time we look at
+        //   this block it seems like the load into it is dead.
+        let block1 = BasicBlock::new(
+            RiscEntry::Label(loop_body),
+            vec![
+                RiscInstruction::Arith(Arith::Add, Var(3), Var(2), Var(2)),
+                RiscInstruction::Load(Var(2), Constant(7)),
+            ],
+            RiscExit::Cond(Cond::Eq, Var(3), Var(0), exit, loop_body),
+        );
+
+        let block2 = BasicBlock::new(RiscEntry::Label(exit), vec![], RiscExit::Ret);
+
+        let graph = Graph::from_blocks(vec![block0, block1, block2]);
+
+        let result = analyze_and_rewrite_backward(
+            &mut DeadLoads,
+            &graph,
+            entry,
+            LiveFact::bottom(),
+            Fuel::unlimited(),
+        );
+
+        assert!(matches!(
+            result.graph[entry].code[..],
+            [
+                RiscInstruction::Load(Var(0), Constant(0)),
+                RiscInstruction::Load(Var(2), Constant(1)),
+            ]
+        ));
+        assert_eq!(result.graph[loop_body].code.len(), 2);
+        assert_eq!(result.facts[&loop_body], live(&[0, 2]));
+    }
+
+    // Turns 'a <= b' into 'b < a' with the branches swapped, and back again, so it always has
+    //   something to rewrite its own output into. Whether it checks can_rewrite() first is up to
+    //   the flag. Everything else is left to DeadLoads.
+    struct FlipCond(bool);
+
+    impl BackwardAnalysis<RiscLanguage, LiveFact> for FlipCond {
+        fn analyze_exit(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            exit: &RiscExit,
+            analyze: AnalyzeExitBackward<LiveFact>,
+        ) -> Option<RewriteExitBackward<RiscLanguage>> {
+            let flipped = match exit {
+                RiscExit::Cond(Cond::Lte, src1, src2, l1, l2) => {
+                    RiscExit::Cond(Cond::Lt, *src2, *src1, *l2, *l1)
+                }
+                RiscExit::Cond(Cond::Lt, src1, src2, l1, l2) => {
+                    RiscExit::Cond(Cond::Lte, *src2, *src1, *l2, *l1)
+                }
+                _ => return DeadLoads.analyze_exit(graph, label, exit, analyze),
+            };
+            if self.0 && !analyze.can_rewrite() {
+                return DeadLoads.analyze_exit(graph, label, exit, analyze);
+            }
+            Some(analyze.replace(flipped))
+        }
+
+        fn analyze_instruction(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            instruction: &RiscInstruction,
+            analyze: AnalyzeInstructionBackward<LiveFact>,
+        ) -> Option<RewriteInstructionBackward<RiscLanguage>> {
+            DeadLoads.analyze_instruction(graph, label, instruction, analyze)
+        }
+
+        fn analyze_entry(
+            &mut self,
+            graph: &Graph<RiscLanguage>,
+            label: Label,
+            entry: &RiscEntry,
+            fact: LiveFact,
+        ) -> FactBase<LiveFact> {
+            DeadLoads.analyze_entry(graph, label, entry, fact)
+        }
+    }
+
+    fn flip_graph() -> Graph<RiscLanguage> {
+        Graph::from_blocks(vec![
+            BasicBlock::new(
+                RiscEntry::Label(Label(0)),
+                vec![
+                    RiscInstruction::Load(Var(0), Constant(1)),
+                    RiscInstruction::Load(Var(1), Constant(2)),
+                ],
+                RiscExit::Cond(Cond::Lte, Var(0), Var(1), Label(1), Label(2)),
+            ),
+            ret(1),
+            ret(2),
+        ])
+    }
+
+    #[test]
+    fn refused_exit_test() {
+        // The flipped exit is too deep to flip back, so it has to be analyzed as it is, which
+        //   keeps both of the loads feeding it.
+        let result = analyze_and_rewrite_backward(
+            &mut FlipCond(true),
+            &flip_graph(),
+            Label(0),
+            LiveFact::bottom(),
+            Fuel::unlimited(),
+        );
+        assert!(matches!(
+            result.graph[Label(0)].exit,
+            RiscExit::Cond(Cond::Lt, Var(1), Var(0), Label(2), Label(1))
+        ));
+        assert_eq!(result.graph[Label(0)].code.len(), 2);
+    }
+
+    // Without checking, the flip back gets refused and nothing marks the variables in the
+    //   condition as live, which would make both loads look dead.
+    #[test]
+    #[should_panic(expected = "can_rewrite() allows it")]
+    fn unchecked_exit_rewrite_test() {
+        analyze_and_rewrite_backward(
+            &mut FlipCond(false),
+            &flip_graph(),
+            Label(0),
+            LiveFact::bottom(),
+            Fuel::unlimited(),
+        );
+    }
+
+    #[test]
+    fn dominator_test() {
+        let block1: BasicBlock<RiscLanguage> =
+            BasicBlock::new(RiscEntry::Label(Label(1)), vec![], RiscExit::Jump(Label(2)));
+
+        let block2: BasicBlock<RiscLanguage> = BasicBlock::new(
+            RiscEntry::Label(Label(2)),
+            vec![],
+            RiscExit::Cond(Cond::Eq, Var(0), Var(1), Label(3), Label(4)),
+        );
+
+        let block3: BasicBlock<RiscLanguage> =
+            BasicBlock::new(RiscEntry::Label(Label(3)), vec![], RiscExit::Jump(Label(5)));
+
+        let block4: BasicBlock<RiscLanguage> =
+            BasicBlock::new(RiscEntry::Label(Label(4)), vec![], RiscExit::Jump(Label(5)));
+
+        let block5: BasicBlock<RiscLanguage> =
+            BasicBlock::new(RiscEntry::Label(Label(5)), vec![], RiscExit::Jump(Label(2)));
+        let graph = Graph::from_blocks(vec![block1, block2, block3, block4, block5]);
+
+        let mut dom_analysis = dominator::DominatorAnalysis;
+        let dominators = forward_analysis(
+            &mut dom_analysis,
+            &graph,
+            Label(1),
+            dominator::DominatorFact::bottom(),
+        );
+
+        println!("dominators {{");
+        for (label, dom) in &dominators {
+            println!("\t{:?}: {:?}", label, dom.dominates);
+        }
+        println!("}}");
+
+        let tree = dominator::DominatorTree::new(&graph, Label(1));
+        assert_eq!(tree.idom(Label(1)), None);
+        assert_eq!(tree.idom(Label(2)), Some(Label(1)));
+        assert_eq!(tree.idom(Label(3)), Some(Label(2)));
+        assert_eq!(tree.idom(Label(4)), Some(Label(2)));
+        assert_eq!(tree.idom(Label(5)), Some(Label(2)));
+
+        assert!(tree.dominates(Label(2), Label(5)));
+        assert!(tree.dominates(Label(5), Label(5)));
+        assert!(!tree.strictly_dominates(Label(5), Label(5)));
+        assert!(!tree.dominates(Label(3), Label(5)));
+        assert!(!tree.dominates(Label(5), Label(2)));
+
+        let mut children = tree.children(Label(2)).to_vec();
+        children.sort_by_key(|label| label.0);
+        assert_eq!(children, vec![Label(3), Label(4), Label(5)]);
+        assert_eq!(tree.depth(Label(1)), Some(0));
+        assert_eq!(tree.depth(Label(5)), Some(2));
+
+        // The tree agrees with the path the analysis works out for the blocks dominating each
+        //   block, other than the entry, which starts with nothing.
+        for (label, dom) in &dominators {
+            if *label == Label(1) {
+                continue;
+            }
+            let mut path = vec![];
+            let mut next = tree.idom(*label);
+            while let Some(idom) = next {
+                path.push(idom);
+                next = tree.idom(idom);
+            }
+            path.reverse();
+            assert_eq!(dom.dominates.as_ref(), Some(&path));
+        }
+    }
+
+    #[test]
+    fn dominator_missing_entry_test() {
+        let graph = Graph::from_blocks(vec![jump(0, 1), ret(1)]);
+        assert!(matches!(
+            dominator::DominatorTree::try_new(&graph, Label(2)),
+            Err(GraphError::MissingEntry(Label(2)))
+        ));
+        assert!(dominator::DominatorTree::try_new(&graph, Label(0)).is_ok());
+    }
+
+    #[test]
+    fn dominance_frontier_test() {
+        // The same loop around a diamond as in dominator_test.
+        let graph = Graph::from_blocks(vec![
+            jump(1, 2),
+            cond(2, 3, 4),
+            jump(3, 5),
+            jump(4, 5),
+            jump(5, 2),
+        ]);
+
+        let tree = dominator::DominatorTree::new(&graph, Label(1));
+        assert_eq!(tree.dominance_frontier(Label(1)), &[]);
+        assert_eq!(tree.dominance_frontier(Label(2)), &[Label(2)]);
+        assert_eq!(tree.dominance_frontier(Label(3)), &[Label(5)]);
+        assert_eq!(tree.dominance_frontier(Label(4)), &[Label(5)]);
+        assert_eq!(tree.dominance_frontier(Label(5)), &[Label(2)]);
+
+        // Assigning on one side of the diamond needs a phi where the sides meet, and then
+        //   another at the top of the loop.
+        assert_eq!(
+            tree.iterated_dominance_frontier(&[Label(3)]),
+            vec![Label(2), Label(5)]
+        );
+        assert_eq!(tree.iterated_dominance_frontier(&[Label(1)]), vec![]);
+    }
+
+    #[test]
+    fn post_dominator_test() {
+        let graph = Graph::from_blocks(vec![
+            cond(1, 2, 3),
+            ret(2),
+            cond(3, 4, 5),
+            // An infinite loop, which never gets to an exit.
+            jump(4, 6),
+            jump(6, 4),
+            jump(5, 7),
+            ret(7),
+        ]);
+
+        let tree = dominator::PostDominatorTree::new(&graph, Label(1));
+
+        // Two different exits, so only the virtual exit post-dominates the start.
+        assert_eq!(tree.ipdom(Label(1)), None);
+        assert_eq!(tree.ipdom(Label(2)), None);
+        assert_eq!(tree.ipdom(Label(3)), None);
+        assert_eq!(tree.ipdom(Label(5)), Some(Label(7)));
+        assert!(tree.post_dominates(Label(7), Label(5)));
+        assert!(!tree.post_dominates(Label(5), Label(3)));
+
+        // One of the blocks in the loop stands in for its exit.
+        assert!(tree.contains(Label(4)) && tree.contains(Label(6)));
+        let (loop_root, loop_child) = if tree.ipdom(Label(4)).is_none() {
+            (Label(4), Label(6))
+        } else {
+            (Label(6), Label(4))
+        };
+        assert_eq!(tree.ipdom(loop_child), Some(loop_root));
+        assert!(tree.strictly_post_dominates(loop_root, loop_child));
+        assert_eq!(tree.depth(loop_child), Some(1));
+
+        let mut roots = tree.roots().to_vec();
+        roots.sort_by_key(|label| label.0);
+        let mut expected = vec![Label(1), Label(2), Label(3), Label(7), loop_root];
+        expected.sort_by_key(|label| label.0);
+        assert_eq!(roots, expected);
+    }
+
+    #[test]
+    fn post_dominator_dangling_test() {
+        // 1 jumps to a block that isn't there, and 3 only goes there some of the time.
+        let graph = Graph::from_blocks(vec![cond(0, 1, 3), jump(1, 2), cond(3, 2, 4), ret(4)]);
+
+        let tree = dominator::PostDominatorTree::new(&graph, Label(0));
+        assert!(tree.contains(Label(1)));
+        assert_eq!(tree.ipdom(Label(0)), None);
+        assert_eq!(tree.ipdom(Label(1)), None);
+        assert_eq!(tree.ipdom(Label(3)), Some(Label(4)));
+    }
+
+    #[test]
+    fn ssa_test() {
+        let entry = Label(0);
+        let loop_body = Label(1);
+        let exit = Label(2);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![
+                RiscInstruction::Load(Var(0), Constant(0)),
+                RiscInstruction::Load(Var(1), Constant(1)),
+                RiscInstruction::Load(Var(2), Constant(5)),
+            ],
+            RiscExit::Jump(loop_body),
+        );
+
+        let block1 = BasicBlock::new(
+            RiscEntry::Label(loop_body),
+            vec![RiscInstruction::Arith(Arith::Sub, Var(2), Var(2), Var(1))],
+            RiscExit::Cond(Cond::Eq, Var(2), Var(0), exit, loop_body),
+        );
+
+        let block2 = BasicBlock::new(RiscEntry::Label(exit), vec![], RiscExit::Ret);
+
+        let graph = Graph::from_blocks(vec![block0, block1, block2]);
+
+        let mut next = 100;
+        let ssa: Graph<RiscLanguage> = ssa::to_ssa(&graph, entry, |_| {
+            next += 1;
+            Var(next - 1)
+        });
+
+        assert!(matches!(
+            ssa[entry].code[..],
+            [
+                RiscInstruction::Load(Var(100), Constant(0)),
+                RiscInstruction::Load(Var(101), Constant(1)),
+                RiscInstruction::Load(Var(102), Constant(5)),
+            ]
+        ));
+
+        // Only Var(2) is assigned inside the loop, so it's the only one that needs a phi.
+        assert_eq!(ssa[loop_body].code.len(), 2);
+        match &ssa[loop_body].code[0] {
+            RiscInstruction::Phi(Var(103), args) => {
+                assert_eq!(args, &vec![(entry, Var(102)), (loop_body, Var(104))]);
+            }
+            other => panic!("Expected a phi, got {:?}", other),
+        }
+        assert!(matches!(
+            ssa[loop_body].code[1],
+            RiscInstruction::Arith(Arith::Sub, Var(104), Var(103), Var(101))
+        ));
+        assert!(matches!(
+            ssa[loop_body].exit,
+            RiscExit::Cond(Cond::Eq, Var(104), Var(100), _, _)
+        ));
+    }
+
+    #[test]
+    fn from_ssa_test() {
+        let entry = Label(0);
+        let header = Label(1);
+        let latch = Label(2);
+        let exit = Label(3);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![
+                RiscInstruction::Load(Var(0), Constant(1)),
+                RiscInstruction::Load(Var(1), Constant(2)),
+            ],
+            RiscExit::Jump(header),
+        );
+
+        // Var(2) and Var(3) swap places every time around the loop.
+        let block1 = BasicBlock::new(
+            RiscEntry::Label(header),
+            vec![
+                RiscInstruction::Phi(Var(2), vec![(entry, Var(0)), (latch, Var(3))]),
+                RiscInstruction::Phi(Var(3), vec![(entry, Var(1)), (latch, Var(2))]),
+            ],
+            RiscExit::Jump(latch),
+        );
+
+        let block2 = BasicBlock::new(
+            RiscEntry::Label(latch),
+            vec![],
+            RiscExit::Cond(Cond::Eq, Var(2), Var(3), exit, header),
+        );
+
+        let block3 = BasicBlock::new(
+            RiscEntry::Label(exit),
+            vec![RiscInstruction::Phi(Var(4), vec![(latch, Var(2))])],
+            RiscExit::Ret,
+        );
+
+        let graph: Graph<RiscLanguage> = Graph::from_blocks(vec![block0, block1, block2, block3]);
+        let result = ssa::from_ssa(&graph, entry, |_| Var(100));
+
+        // The entry only jumps to the header, so its copies can go at the end of it.
+        assert!(matches!(
+            result[entry].code[2..],
+            [
+                RiscInstruction::Move(Var(2), Var(0)),
+                RiscInstruction::Move(Var(3), Var(1)),
+            ]
+        ));
+        assert!(result[header].code.is_empty());
+
+        // The latch can also leave the loop, so the edge back gets split, and the swap needs
+        //   a temporary.
+        let split = match result[latch].exit {
+            RiscExit::Cond(Cond::Eq, Var(2), Var(3), l1, l2) if l1 == exit => l2,
+            ref other => panic!("Expected the latch to branch to the split, got {:?}", other),
+        };
+        assert_eq!(split, Label(4));
+        assert!(matches!(result[split].exit, RiscExit::Jump(l) if l == header));
+        assert!(matches!(
+            result[split].code[..],
+            [
+                RiscInstruction::Move(Var(100), Var(2)),
+                RiscInstruction::Move(Var(2), Var(3)),
+                RiscInstruction::Move(Var(3), Var(100)),
+            ]
+        ));
+
+        // The latch is the only way out of the loop though, so that edge doesn't need a block
+        //   of its own.
+        assert!(matches!(
+            result[exit].code[..],
+            [RiscInstruction::Move(Var(4), Var(2))]
+        ));
+        assert_eq!(result.labels().count(), 5);
+    }
+
+    #[test]
+    fn from_ssa_args_test() {
+        // Both ways into 2 pass it an argument, so their copies can't go in front of the exits.
+        let graph: Graph<RiscLanguage> = Graph::from_blocks(vec![
+            BasicBlock::new(
+                RiscEntry::Label(Label(0)),
+                vec![],
+                RiscExit::CondWith(
+                    Cond::Eq,
+                    Var(0),
+                    Var(1),
+                    (Label(1), vec![]),
+                    (Label(2), vec![Var(0)]),
+                ),
+            ),
+            BasicBlock::new(
+                RiscEntry::Label(Label(1)),
+                vec![],
+                RiscExit::JumpWith(Label(2), vec![Var(1)]),
+            ),
+            BasicBlock::new(
+                RiscEntry::Params(Label(2), vec![Var(5)]),
+                vec![RiscInstruction::Phi(
+                    Var(6),
+                    vec![(Label(0), Var(0)), (Label(1), Var(1))],
+                )],
+                RiscExit::Ret,
+            ),
+        ]);
+        let result = ssa::from_ssa(&graph, Label(0), |_| Var(100));
+        assert!(result[Label(2)].code.is_empty());
+
+        // Each split gets the copy for its edge, and passes on the argument.
+        for (from, src) in [(0, Var(0)), (1, Var(1))] {
+            let split = result[Label(from)]
+                .successors()
+                .into_iter()
+                .find(|successor| successor.0 > 2)
+                .expect("The edge to 2 should have been split");
+            assert_eq!(
+                RiscLanguage::exit_args(&result[Label(from)].exit, split),
+                vec![]
+            );
+            assert!(matches!(
+                result[split].code[..],
+                [RiscInstruction::Move(Var(6), var)] if var == src
+            ));
+            assert_eq!(
+                RiscLanguage::edge_bindings(&result[split].exit, &result[Label(2)].entry),
+                vec![(Var(5), src)]
+            );
+        }
+    }
+
+    #[test]
+    fn predecessors_test() {
+        let graph = Graph::from_blocks(vec![
+            jump(1, 2),
+            cond(2, 3, 4),
+            jump(3, 5),
+            jump(4, 5),
+            // Loops back on itself, and to the top of the diamond.
+            cond(5, 5, 2),
+            // Both sides of the branch go to the same place.
+            cond(6, 7, 7),
+            ret(7),
+        ]);
+
+        assert_eq!(graph.predecessors(Label(1)), &[]);
+        assert_eq!(graph.predecessors(Label(2)), &[Label(1), Label(5)]);
+        assert_eq!(graph.predecessors(Label(3)), &[Label(2)]);
+        assert_eq!(
+            graph.predecessors(Label(5)),
+            &[Label(3), Label(4), Label(5)]
+        );
+        assert_eq!(graph.predecessors(Label(7)), &[Label(6)]);
+        assert_eq!(graph.predecessors(Label(8)), &[]);
+        assert_eq!(
+            graph.direct_predecessors(Label(5)),
+            vec![Label(3), Label(4), Label(5)]
+        );
+
+        // Caching the predecessors doesn't stop graphs from being shared between threads.
+        fn shareable<T: Send + Sync>(_: &T) {}
+        shareable(&graph);
+    }
+
+    #[test]
+    fn block_params_test() {
+        let entry = Label(0);
+        let join = Label(1);
+        let left = Label(2);
+        let right = Label(3);
+
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![RiscInstruction::Load(Var(0), Constant(3))],
+            RiscExit::JumpWith(join, vec![Var(0)]),
+        );
+        let block1 = BasicBlock::new(
+            RiscEntry::Params(join, vec![Var(1)]),
+            vec![RiscInstruction::Arith(Arith::Add, Var(2), Var(1), Var(1))],
+            RiscExit::Ret,
+        );
+        let graph = Graph::from_blocks(vec![block0, block1.clone()]);
+
+        let result = analyze_and_rewrite_forward(
+            &mut ConstantPropagation,
+            &graph,
+            entry,
+            ConstFact::new(),
+            Fuel::unlimited(),
+        );
+        assert_eq!(result.facts[&join].get_const(Var(1)), Some(Constant(3)));
+        assert!(matches!(
+            result.graph[join].code[..],
+            [RiscInstruction::Load(Var(2), Constant(6))]
+        ));
+
+        // Once another block passes something else, the parameter isn't constant anymore.
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![RiscInstruction::Load(Var(0), Constant(3))],
+            RiscExit::Cond(Cond::Eq, Var(0), Var(0), left, right),
+        );
+        let block2 = BasicBlock::new(
+            RiscEntry::Label(left),
+            vec![],
+            RiscExit::JumpWith(join, vec![Var(0)]),
+        );
+        let block3 = BasicBlock::new(
+            RiscEntry::Label(right),
+            vec![RiscInstruction::Load(Var(3), Constant(4))],
+            RiscExit::JumpWith(join, vec![Var(3)]),
+        );
+        let graph = Graph::from_blocks(vec![block0, block1, block2, block3]);
+
+        let result = analyze_and_rewrite_forward(
+            &mut ConstantPropagation,
+            &graph,
+            entry,
+            ConstFact::new(),
+            Fuel::unlimited(),
+        );
+        assert_eq!(result.facts[&join].get(Var(1)), Some(WithTop::Top));
+        assert!(matches!(
+            result.graph[join].code[..],
+            [RiscInstruction::Arith(Arith::Add, Var(2), Var(1), Var(1))]
+        ));
+    }
+
+    #[test]
+    fn entry_edges_test() {
+        let entry = Label(0);
+        let left = Label(1);
+        let right = Label(2);
+        let join = Label(3);
+        let exit = Label(4);
+
+        // Var(1) is different on each side, but the phi picks Var(2) on the right, which
+        //   agrees with Var(1) on the left.
+        let block0 = BasicBlock::new(
+            RiscEntry::Label(entry),
+            vec![RiscInstruction::Load(Var(0), Constant(0))],
+            RiscExit::Cond(Cond::Eq, Var(0), Var(0), left, right),
+        );
+        let block1 = BasicBlock::new(
+            RiscEntry::Label(left),
+            vec![RiscInstruction::Load(Var(1), Constant(3))],
+            RiscExit::Jump(join),
+        );
+        let block2 = BasicBlock::new(
+            RiscEntry::Label(right),
+            vec![
+                RiscInstruction::Load(Var(1), Constant(4)),
+                RiscInstruction::Load(Var(2), Constant(3)),
+            ],
+            RiscExit::Jump(join),
+        );
+        let block3 = BasicBlock::new(
+            RiscEntry::Label(join),
+            vec![
+                RiscInstruction::Phi(Var(3), vec![(left, Var(1)), (right, Var(2))]),
+                RiscInstruction::Phi(Var(4), vec![(left, Var(1)), (right, Var(1))]),
+                RiscInstruction::Arith(Arith::Add, Var(5), Var(3), Var(3)),
+            ],
+            RiscExit::Jump(exit),
+        );
+        let block4 = BasicBlock::new(RiscEntry::Label(exit), vec![], RiscExit::Ret);
+        let graph = Graph::from_blocks(vec![block0, block1, block2, block3, block4]);
+
+        let result = analyze_and_rewrite_forward(
+            &mut ConstantPropagation,
+            &graph,
+            entry,
+            ConstFact::new(),
+            Fuel::unlimited(),
+        );
+
+        // Joined together, Var(1) isn't constant, so only the phi can tell Var(3) is.
+        assert_eq!(result.facts[&join].get(Var(1)), Some(WithTop::Top));
+        let fact = &result.facts[&exit];
+        assert_eq!(fact.get_const(Var(3)), Some(Constant(3)));
+        assert_eq!(fact.get(Var(4)), Some(WithTop::Top));
+        assert!(matches!(
+            result.graph[join].code[2],
+            RiscInstruction::Load(Var(5), Constant(6))
+        ));
+
+        // Pair splits the incoming facts up between its halves.
+        let facts = forward_analysis(
+            &mut Pair(ConstantPropagation, dominator::DominatorAnalysis),
+            &graph,
+            entry,
+            (ConstFact::new(), dominator::DominatorFact::bottom()),
+        );
+        assert_eq!(facts[&exit].0.get_const(Var(3)), Some(Constant(3)));
+    }
+
+    #[test]
+    fn loops_test() {
+        // An outer loop at 1 with two latches, an inner loop at 2, and a loop at 6 that jumps
+        //   to itself.
+        let graph = Graph::from_blocks(vec![
+            jump(0, 1),
+            cond(1, 2, 6),
+            jump(2, 3),
+            cond(3, 2, 4),
+            cond(4, 1, 5),
+            jump(5, 1),
+            cond(6, 6, 7),
+            ret(7),
+        ]);
+        let dominators = dominator::DominatorTree::new(&graph, Label(0));
+        let loops = loops::LoopInfo::new(&graph, &dominators);
+
+        assert_eq!(loops.headers(), &[Label(1), Label(2), Label(6)]);
+        assert_eq!(loops.roots(), &[Label(1), Label(6)]);
+        assert!(!loops.is_header(Label(3)));
+
+        assert_eq!(loops.latches(Label(1)), &[Label(4), Label(5)]);
+        assert_eq!(
+            loops.body(Label(1)),
+            &[Label(1), Label(2), Label(3), Label(4), Label(5)]
+        );
+        assert_eq!(loops.exits(Label(1)), &[Label(6)]);
+        assert_eq!(loops.children(Label(1)), &[Label(2)]);
+
+        assert_eq!(loops.latches(Label(2)), &[Label(3)]);
+        assert_eq!(loops.body(Label(2)), &[Label(2), Label(3)]);
+        assert_eq!(loops.exits(Label(2)), &[Label(4)]);
+        assert_eq!(loops.parent(Label(2)), Some(Label(1)));
+
+        assert_eq!(loops.latches(Label(6)), &[Label(6)]);
+        assert_eq!(loops.body(Label(6)), &[Label(6)]);
+        assert_eq!(loops.parent(Label(6)), None);
+
+        assert!(loops.contains(Label(1), Label(3)));
+        assert!(!loops.contains(Label(2), Label(4)));
+        assert_eq!(loops.innermost(Label(3)), Some(Label(2)));
+        assert_eq!(loops.innermost(Label(7)), None);
+        let depths: Vec<usize> = (0..8).map(|label| loops.depth(Label(label))).collect();
+        assert_eq!(depths, vec![0, 1, 2, 2, 1, 1, 1, 0]);
+
+        // A cycle that can be entered from two places doesn't have a header that dominates the
+        //   rest of it, so it isn't a natural loop.
+        let graph = Graph::from_blocks(vec![cond(0, 1, 2), jump(1, 2), jump(2, 1)]);
+        let dominators = dominator::DominatorTree::new(&graph, Label(0));
+        let loops = loops::LoopInfo::new(&graph, &dominators);
+        assert!(loops.headers().is_empty());
+        assert_eq!(loops.depth(Label(1)), 0);
+    }
+
+    #[test]
+    fn irreducible_test() {
+        // Branches that load something, so we can tell the copies apart.
+        let branch = |from: u32, to1: u32, to2: u32| -> BasicBlock<RiscLanguage> {
+            let mut block = cond(from, to1, to2);
+            block.code.push(RiscInstruction::Load(
+                Var(from as u16),
+                Constant(from as usize),
+            ));
+            block
+        };
+
+        // 1 and 2 jump to each other, and the entry can get into the cycle at either of them.
+        let graph = Graph::from_blocks(vec![branch(0, 1, 2), jump(1, 2), branch(2, 1, 3), ret(3)]);
+        assert!(!irreducible::is_reducible(&graph, Label(0)));
+        assert_eq!(
+            irreducible::irreducible_regions(&graph, Label(0)),
+            vec![irreducible::IrreducibleRegion {
+                entries: vec![Label(1), Label(2)],
+                body: vec![Label(1), Label(2)],
+            }]
+        );
+
+        // 1 is the header, so the entry jumps to a copy of 2 instead, which goes back into the
+        //   loop through 1.
+        let result = irreducible::split_nodes(&graph, Label(0));
+        assert!(irreducible::is_reducible(&result, Label(0)));
+        assert_eq!(result[Label(0)].successors(), vec![Label(1), Label(4)]);
+        assert_eq!(result[Label(4)].successors(), vec![Label(1), Label(3)]);
+        assert!(matches!(
+            result[Label(4)].code[..],
+            [RiscInstruction::Load(Var(2), Constant(2))]
+        ));
+        let dominators = dominator::DominatorTree::new(&result, Label(0));
+        let loops = loops::LoopInfo::new(&result, &dominators);
+        assert_eq!(loops.headers(), &[Label(1)]);
+        assert_eq!(loops.body(Label(1)), &[Label(1), Label(2)]);
+
+        // The same thing, but inside of a loop at 1, which is fine on its own.
+        let graph = Graph::from_blocks(vec![
+            jump(0, 1),
+            branch(1, 2, 3),
+            jump(2, 3),
+            branch(3, 2, 4),
+            branch(4, 1, 5),
+            ret(5),
+        ]);
+        assert_eq!(
+            irreducible::irreducible_regions(&graph, Label(0)),
+            vec![irreducible::IrreducibleRegion {
+                entries: vec![Label(2), Label(3)],
+                body: vec![Label(2), Label(3)],
+            }]
+        );
+        let result = irreducible::split_nodes(&graph, Label(0));
+        assert!(irreducible::is_reducible(&result, Label(0)));
+        let dominators = dominator::DominatorTree::new(&result, Label(0));
+        let loops = loops::LoopInfo::new(&result, &dominators);
+        assert_eq!(loops.headers(), &[Label(1), Label(2)]);
+        assert_eq!(loops.parent(Label(2)), Some(Label(1)));
+    }
+
+    #[test]
+    fn scc_test() {
+        // Nothing gets to 4 and 5, or 6 which only jumps to itself, or 7.
+        let graph = Graph::from_blocks(vec![
+            jump(0, 1),
+            jump(1, 2),
+            cond(2, 1, 3),
+            ret(3),
+            jump(4, 5),
+            cond(5, 4, 3),
+            jump(6, 6),
+            ret(7),
+        ]);
+
+        let components = scc::strongly_connected_components(&graph);
+        assert_eq!(
+            components,
+            vec![
+                vec![Label(7)],
+                vec![Label(6)],
+                vec![Label(4), Label(5)],
+                vec![Label(0)],
+                vec![Label(1), Label(2)],
+                vec![Label(3)],
+            ]
+        );
+
+        let condensation = scc::Condensation::new(&graph);
+        assert_eq!(condensation.components(), &components[..]);
+        for from in 0..8 {
+            let from_component = condensation.component(Label(from)).unwrap();
+            for to in graph[Label(from)].successors() {
+                assert!(from_component <= condensation.component(to).unwrap());
+            }
+        }
+        let loop_component = condensation.component(Label(2)).unwrap();
+        assert_eq!(condensation.labels(loop_component), &[Label(1), Label(2)]);
+        assert!(condensation.is_cycle(loop_component));
+        assert!(condensation.is_cycle(condensation.component(Label(6)).unwrap()));
+        assert!(!condensation.is_cycle(condensation.component(Label(0)).unwrap()));
+        assert_eq!(
+            condensation.predecessors(condensation.component(Label(3)).unwrap()),
+            &[2, 4]
+        );
+        assert_eq!(condensation.successors(loop_component), &[5]);
+
+        assert_eq!(
+            scc::unreachable_cycles(&graph, Label(0)),
+            vec![vec![Label(4), Label(5)], vec![Label(6)]]
+        );
+    }
+
+    #[test]
+    fn control_dependence_test() {
+        // A diamond, and then a loop at 3.
+        let graph = Graph::from_blocks(vec![
+            cond(0, 1, 2),
+            jump(1, 3),
+            jump(2, 3),
+            cond(3, 4, 5),
+            jump(4, 3),
+            ret(5),
+        ]);
+        let post_dominators = dominator::PostDominatorTree::new(&graph, Label(0));
+        let control = control_dependence::ControlDependence::new(&graph, &post_dominators);
+
+        assert_eq!(control.dependences(Label(0)), &[]);
+        assert_eq!(control.dependences(Label(1)), &[(Label(0), Label(1))]);
+        assert_eq!(control.dependences(Label(2)), &[(Label(0), Label(2))]);
+        assert_eq!(control.dependences(Label(3)), &[(Label(3), Label(4))]);
+        assert_eq!(control.dependences(Label(4)), &[(Label(3), Label(4))]);
+        assert_eq!(control.dependences(Label(5)), &[]);
+
+        assert_eq!(
+            control.dependents(Label(3), Label(4)),
+            &[Label(3), Label(4)]
+        );
+        assert_eq!(control.dependents(Label(3), Label(5)), &[]);
+    }
+
+    #[test]
+    fn critical_edges_test() {
+        // 0 skips over 1 to get to 2, and 2 loops back on itself.
+        let graph = Graph::from_blocks(vec![cond(0, 1, 2), jump(1, 2), cond(2, 2, 3), ret(3)]);
+        assert!(!critical_edges::is_critical_edge(
+            &graph,
+            Label(0),
+            Label(1)
+        ));
+        assert!(critical_edges::is_critical_edge(&graph, Label(0), Label(2)));
+        assert!(!critical_edges::is_critical_edge(
+            &graph,
+            Label(1),
+            Label(2)
+        ));
+        assert!(critical_edges::is_critical_edge(&graph, Label(2), Label(2)));
+
+        let result = critical_edges::split_critical_edges(&graph);
+        assert_eq!(result[Label(0)].successors(), vec![Label(1), Label(4)]);
+        assert_eq!(result[Label(4)].successors(), vec![Label(2)]);
+        assert_eq!(result[Label(2)].successors(), vec![Label(5), Label(3)]);
+        assert_eq!(result[Label(5)].successors(), vec![Label(2)]);
+        assert_eq!(result[Label(1)].successors(), vec![Label(2)]);
+        for from in [0, 1, 2, 4, 5] {
+            for to in result[Label(from)].successors() {
+                assert!(!critical_edges::is_critical_edge(&result, Label(from), to));
+            }
+        }
+        assert!(!critical_edges::is_critical_edge(
+            &result,
+            Label(9),
+            Label(2)
+        ));
+    }
+
+    #[test]
+    fn critical_edge_args_test() {
+        // 0 passes a different argument to 2 than 1 does, straight across a critical edge.
+        let graph: Graph<RiscLanguage> = Graph::from_blocks(vec![
+            BasicBlock::new(
+                RiscEntry::Label(Label(0)),
+                vec![],
+                RiscExit::CondWith(
+                    Cond::Eq,
+                    Var(0),
+                    Var(1),
+                    (Label(1), vec![Var(0)]),
+                    (Label(2), vec![Var(1)]),
+                ),
+            ),
+            BasicBlock::new(
+                RiscEntry::Params(Label(1), vec![Var(5)]),
+                vec![],
+                RiscExit::JumpWith(Label(2), vec![Var(5)]),
+            ),
+            BasicBlock::new(
+                RiscEntry::Params(Label(2), vec![Var(6)]),
+                vec![],
+                RiscExit::Ret,
+            ),
+        ]);
+
+        let result = critical_edges::split_critical_edges(&graph);
+        assert_eq!(result[Label(0)].successors(), vec![Label(1), Label(3)]);
+        assert_eq!(
+            RiscLanguage::exit_args(&result[Label(0)].exit, Label(3)),
+            vec![]
+        );
+        assert_eq!(
+            RiscLanguage::exit_args(&result[Label(0)].exit, Label(1)),
+            vec![Var(0)]
+        );
+
+        // The new block passes on what 0 used to.
+        assert_eq!(
+            RiscLanguage::edge_bindings(&result[Label(3)].exit, &result[Label(2)].entry),
+            vec![(Var(6), Var(1))]
+        );
+    }
+
+    #[test]
+    fn label_supply_test() {
+        let graph: Graph<RiscLanguage> = Graph::from_blocks(vec![jump(0, 3), ret(3)]);
+        assert_eq!(graph.fresh_label(), Label(4));
+        assert_eq!(graph.fresh_label(), Label(5));
+
+        // Clones share the supply, so labels from either never run into each other.
+        let copy = graph.clone();
+        assert_eq!(copy.fresh_label(), Label(6));
+        assert_eq!(graph.fresh_label(), Label(7));
+
+        // A sub-graph that uses the same labels as the graph, and jumps out to 3 at the end.
+        let sub_graph: Graph<RiscLanguage> = Graph::from_blocks(vec![
+            BasicBlock::new(
+                RiscEntry::Label(Label(0)),
+                vec![RiscInstruction::Load(Var(0), Constant(1))],
+                RiscExit::Cond(Cond::Eq, Var(0), Var(0), Label(1), Label(3)),
+            ),
+            BasicBlock::new(
+                RiscEntry::Params(Label(1), vec![Var(1)]),
+                vec![],
+                RiscExit::Jump(Label(3)),
+            ),
+        ]);
+        let (renamed, labels) = sub_graph.rename_labels(graph.label_supply());
+        assert_eq!(labels[&Label(0)], Label(8));
+        assert_eq!(labels[&Label(1)], Label(9));
+        assert!(!renamed.contains(Label(0)));
+        assert_eq!(renamed[Label(8)].successors(), vec![Label(9), Label(3)]);
+        assert!(matches!(
+            renamed[Label(9)].entry,
+            RiscEntry::Params(Label(9), ref params) if params[..] == [Var(1)]
+        ));
+        assert!(matches!(
+            renamed[Label(8)].code[..],
+            [RiscInstruction::Load(Var(0), Constant(1))]
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Every label has already been handed out")]
+    fn label_supply_overflow_test() {
+        // Blocks can use the highest labels, there just isn't anything left to hand out after.
+        let mut graph: Graph<RiscLanguage> = Graph::from_blocks(vec![ret(u32::MAX - 2)]);
+        assert_eq!(graph.fresh_label(), Label(u32::MAX - 1));
+        graph.insert_block(ret(u32::MAX));
+
+        let graph: Graph<RiscLanguage> = Graph::from_blocks(vec![ret(0), ret(u32::MAX)]);
+        graph.fresh_label();
+    }
+
+    #[test]
+    fn graph_mutation_test() {
+        let mut graph = Graph::from_blocks(vec![cond(0, 1, 2), jump(1, 2), ret(2)]);
+        assert_eq!(graph.predecessors(Label(2)), &[Label(0), Label(1)]);
+
+        // Put a block in front of 2, and send everything there instead.
+        let label = graph.fresh_label();
+        graph.redirect_all(Label(2), label);
+        assert!(graph.insert_block(jump(label.0, 2)).is_none());
+        assert_eq!(graph.predecessors(Label(2)), &[label]);
+        assert_eq!(graph.predecessors(label), &[Label(0), Label(1)]);
+        assert_eq!(graph[Label(0)].successors(), vec![Label(1), label]);
+
+        // Just the one edge this time.
+        graph.redirect(Label(0), Label(1), Label(2));
+        assert_eq!(graph[Label(0)].successors(), vec![Label(2), label]);
+        assert_eq!(graph.predecessors(Label(1)), &[]);
+
+        let removed = graph.remove_block(Label(1)).unwrap();
+        assert!(matches!(removed.exit, RiscExit::Jump(l) if l == label));
+        assert!(!graph.contains(Label(1)));
+        assert_eq!(graph.predecessors(label), &[Label(0)]);
+
+        graph
+            .get_mut(label)
+            .unwrap()
+            .code
+            .push(RiscInstruction::Load(Var(0), Constant(1)));
+        graph[Label(2)]
+            .code
+            .push(RiscInstruction::Load(Var(1), Constant(2)));
+        assert_eq!(graph[label].code.len(), 1);
+        assert_eq!(graph[Label(2)].code.len(), 1);
+
+        let mut labels: Vec<u32> = graph.iter().map(|(label, _)| label.0).collect();
+        labels.sort();
+        assert_eq!(labels, vec![0, 2, 3]);
+        assert!(graph.iter().all(|(label, block)| block.label() == label));
+
+        // Inserting a block takes its label out of the supply, even in a clone.
+        let mut copy = graph.clone();
+        copy.insert_block(jump(10, 2));
+        assert_eq!(graph.fresh_label(), Label(11));
+    }
+
+    #[test]
+    fn validate_test() {
+        let graph = Graph::try_from_blocks(vec![jump(0, 1), ret(1)], Label(0)).unwrap();
+        assert_eq!(graph.validate(Label(0)), Ok(()));
+
+        assert_eq!(
+            Graph::try_from_blocks(vec![jump(0, 1), ret(1), ret(1)], Label(0)).err(),
+            Some(GraphError::DuplicateLabel(Label(1)))
+        );
+        assert_eq!(
+            graph.validate(Label(2)),
+            Err(GraphError::MissingEntry(Label(2)))
+        );
+
+        // Walking the graph stops at jumps to blocks that aren't there, rather than panicking.
+        let dangling = Graph::from_blocks(vec![jump(0, 1), jump(1, 2)]);
+        assert_eq!(
+            dangling.validate(Label(0)),
+            Err(GraphError::DanglingSuccessor {
+                from: Label(1),
+                to: Label(2),
+            })
+        );
+        assert_eq!(
+            dangling.post_order_traversal(Label(0)),
+            vec![Label(1), Label(0)]
+        );
+
+        let mut mismatched = graph.clone();
+        mismatched[Label(1)].entry = RiscEntry::Label(Label(5));
+        let error = mismatched.validate(Label(0)).unwrap_err();
+        assert_eq!(
+            error,
+            GraphError::EntryLabelMismatch {
+                label: Label(1),
+                entry: Label(5),
+            }
+        );
+        assert_eq!(error.to_string(), "the block at L1 is labelled L5");
+    }
+}